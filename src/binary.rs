@@ -0,0 +1,80 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Number of leading bytes inspected when sniffing for binary content.
+pub const SNIFF_LEN: usize = 8192;
+
+/// Fraction of control bytes above which a sample is considered binary.
+pub const NON_TEXT_RATIO: f64 = 0.3;
+
+/// Length of the lines base64 content is wrapped at, as in MIME.
+const BASE64_LINE_LEN: usize = 76;
+
+/// The note, written as a comment, that the lines after it are the base64
+/// of a binary file of `len` bytes.
+pub fn base64_marker(len: usize) -> String {
+    format!("[base64, {} bytes]", len)
+}
+
+/// Encodes `contents` as base64 in lines of `BASE64_LINE_LEN` characters.
+pub fn to_base64_lines(contents: &[u8]) -> Vec<u8> {
+    let encoded = STANDARD.encode(contents);
+    let mut lines = Vec::with_capacity(encoded.len() + encoded.len() / BASE64_LINE_LEN + 1);
+    for line in encoded.as_bytes().chunks(BASE64_LINE_LEN) {
+        lines.extend_from_slice(line);
+        lines.push(b'\n');
+    }
+    lines
+}
+
+/// Reads up to `SNIFF_LEN` bytes from `path` and checks them with `looks_binary`.
+pub fn is_binary(path: &Path) -> io::Result<bool> {
+    let mut buffer = Vec::with_capacity(SNIFF_LEN);
+    File::open(path)?
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut buffer)?;
+    Ok(looks_binary(&buffer))
+}
+
+/// Treats any NUL byte, or a high ratio of non-whitespace control bytes, as binary.
+/// Bytes >= 0x80 are counted as text so UTF-8 content isn't misdetected.
+pub fn looks_binary(sample: &[u8]) -> bool {
+    if sample.is_empty() {
+        return false;
+    }
+    if sample.contains(&0) {
+        return true;
+    }
+
+    let non_text = sample
+        .iter()
+        .filter(|&&b| (b < 0x20 && !matches!(b, b'\t' | b'\n' | b'\r' | 0x0c | 0x1b)) || b == 0x7f)
+        .count();
+    non_text as f64 / sample.len() as f64 > NON_TEXT_RATIO
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_is_not_binary() {
+        assert!(!looks_binary(b""));
+        assert!(!looks_binary(b"fn main() {\n\tprintln!(\"hi\");\r\n}\n"));
+        assert!(!looks_binary("héllo wörld".as_bytes()));
+    }
+
+    #[test]
+    fn test_nul_byte_is_binary() {
+        assert!(looks_binary(b"text\0more text"));
+    }
+
+    #[test]
+    fn test_control_byte_ratio() {
+        assert!(looks_binary(&[0x01, 0x02, 0x03, b'a']));
+        assert!(!looks_binary(&[0x01, b'a', b'b', b'c', b'd']));
+    }
+}
@@ -0,0 +1,60 @@
+use std::io::{self, Write};
+
+/// Passes writes through to `inner`, also keeping a copy of them in `copy` when given.
+pub struct Tee<'a, W> {
+    inner: W,
+    copy: Option<&'a mut Vec<u8>>,
+}
+
+impl<'a, W: Write> Tee<'a, W> {
+    pub fn new(inner: W, copy: Option<&'a mut Vec<u8>>) -> Self {
+        Tee { inner, copy }
+    }
+}
+
+impl<W: Write> Write for Tee<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        if let Some(copy) = &mut self.copy {
+            copy.extend_from_slice(&buf[..written]);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Puts `text` on the system clipboard, replacing invalid UTF-8.
+#[cfg(feature = "clipboard")]
+pub fn copy(text: &[u8]) -> io::Result<()> {
+    arboard::Clipboard::new()
+        .and_then(|mut clipboard| clipboard.set_text(String::from_utf8_lossy(text)))
+        .map_err(io::Error::other)
+}
+
+#[cfg(not(feature = "clipboard"))]
+pub fn copy(_text: &[u8]) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "concacti was built without clipboard support (enable the `clipboard` feature)",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tee() {
+        let mut copy = Vec::new();
+        let mut output = Vec::new();
+        write!(Tee::new(&mut output, Some(&mut copy)), "hello").unwrap();
+        assert_eq!(output, b"hello");
+        assert_eq!(copy, b"hello");
+
+        let mut tee = Tee::new(Vec::new(), None);
+        assert_eq!(tee.write(b"world").unwrap(), 5);
+    }
+}
@@ -0,0 +1,268 @@
+use std::path::Path;
+
+/// How a language writes comments.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CommentSyntax {
+    /// Prefix of a comment running to the end of the line, e.g. `//`.
+    pub line: Option<&'static str>,
+    /// Opening and closing delimiters of a block comment, e.g. `/*` and `*/`.
+    pub block: Option<(&'static str, &'static str)>,
+    /// Characters that open and close string literals, inside which comment
+    /// markers are left alone.
+    pub quotes: &'static [u8],
+    /// Whether `'` followed by an identifier without a closing quote is a
+    /// Rust lifetime or label rather than the start of a literal.
+    pub lifetimes: bool,
+}
+
+const C_LIKE: CommentSyntax = CommentSyntax {
+    line: Some("//"),
+    block: Some(("/*", "*/")),
+    quotes: b"\"'`",
+    lifetimes: false,
+};
+
+const RUST: CommentSyntax = CommentSyntax {
+    lifetimes: true,
+    ..C_LIKE
+};
+
+const HASH: CommentSyntax = CommentSyntax {
+    line: Some("#"),
+    block: None,
+    quotes: b"\"'",
+    lifetimes: false,
+};
+
+const MARKUP: CommentSyntax = CommentSyntax {
+    line: None,
+    block: Some(("<!--", "-->")),
+    quotes: b"",
+    lifetimes: false,
+};
+
+const CSS: CommentSyntax = CommentSyntax {
+    line: None,
+    block: Some(("/*", "*/")),
+    quotes: b"\"'",
+    lifetimes: false,
+};
+
+/// Maps file extensions to their comment syntax.
+const COMMENT_SYNTAXES: &[(&[&str], CommentSyntax)] = &[
+    (
+        &[
+            "ts", "tsx", "js", "jsx", "c", "h", "cpp", "hpp", "cs", "java", "go", "kt", "swift",
+            "scss",
+        ],
+        C_LIKE,
+    ),
+    (&["rs"], RUST),
+    (
+        &[
+            "py", "sh", "bash", "zsh", "rb", "yaml", "yml", "toml", "pl", "r",
+        ],
+        HASH,
+    ),
+    (&["html", "htm", "xml", "md", "svg", "vue"], MARKUP),
+    (&["css"], CSS),
+];
+
+/// Looks up the comment syntax for `path` by its extension.
+pub fn syntax_for(path: &Path) -> Option<CommentSyntax> {
+    let ext = path.extension()?.to_str()?;
+    COMMENT_SYNTAXES
+        .iter()
+        .find(|(extensions, _)| {
+            extensions
+                .iter()
+                .any(|known| known.eq_ignore_ascii_case(ext))
+        })
+        .map(|(_, syntax)| *syntax)
+}
+
+/// Returns the opening and closing delimiters to wrap a single-line comment
+/// in, preferring line comments. The closing delimiter is empty for line comments.
+pub fn delimiters_for(path: &Path) -> Option<(&'static str, &'static str)> {
+    let syntax = syntax_for(path)?;
+    syntax.line.map(|line| (line, "")).or(syntax.block)
+}
+
+/// Removes line and block comments from `contents`, leaving string literals
+/// intact. Lines that held nothing but a comment are removed entirely. A
+/// `#!` line starting the file is kept, since it says how to run it.
+///
+/// String handling is deliberately simple: a quote opens a literal that ends at
+/// the same unescaped quote, and single-quoted literals never span lines (so
+/// that Rust lifetimes don't swallow the rest of the file).
+pub fn strip_comments(contents: &[u8], syntax: CommentSyntax) -> Vec<u8> {
+    let line = syntax.line.map(str::as_bytes);
+    let block = syntax
+        .block
+        .map(|(open, close)| (open.as_bytes(), close.as_bytes()));
+
+    let mut stripped = Vec::with_capacity(contents.len());
+    let mut i = 0;
+    if contents.starts_with(b"#!") {
+        i = contents
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(contents.len(), |end| end + 1);
+        stripped.extend_from_slice(&contents[..i]);
+    }
+    while i < contents.len() {
+        let rest = &contents[i..];
+
+        if syntax.lifetimes && is_lifetime(rest) {
+            stripped.push(rest[0]);
+            i += 1;
+            continue;
+        }
+        if syntax.quotes.contains(&rest[0]) {
+            let end = string_end(rest);
+            stripped.extend_from_slice(&rest[..end]);
+            i += end;
+            continue;
+        }
+
+        let comment_len = if line.is_some_and(|line| rest.starts_with(line)) {
+            Some(rest.iter().position(|&b| b == b'\n').unwrap_or(rest.len()))
+        } else if let Some((open, close)) = block.filter(|(open, _)| rest.starts_with(open)) {
+            Some(
+                find(&rest[open.len()..], close)
+                    .map_or(rest.len(), |end| open.len() + end + close.len()),
+            )
+        } else {
+            None
+        };
+
+        match comment_len {
+            Some(len) => {
+                while stripped.last().is_some_and(|&b| b == b' ' || b == b'\t') {
+                    stripped.pop();
+                }
+                i += len;
+                // Drop the line break too if the comment was alone on its line.
+                let line_start = stripped.is_empty() || stripped.ends_with(b"\n");
+                if line_start && contents.get(i) == Some(&b'\n') {
+                    i += 1;
+                }
+            }
+            None => {
+                stripped.push(rest[0]);
+                i += 1;
+            }
+        }
+    }
+    stripped
+}
+
+/// Whether `rest` starts with a lifetime such as `'a` or `'static`, as
+/// opposed to a character literal such as `'a'`.
+fn is_lifetime(rest: &[u8]) -> bool {
+    rest.first() == Some(&b'\'')
+        && rest
+            .get(1)
+            .is_some_and(|&b| b.is_ascii_alphabetic() || b == b'_')
+        && rest.get(2) != Some(&b'\'')
+}
+
+/// Length of the string literal at the start of `rest`, including its quotes.
+fn string_end(rest: &[u8]) -> usize {
+    let quote = rest[0];
+    let mut i = 1;
+    while i < rest.len() {
+        match rest[i] {
+            b'\\' => i += 2,
+            b'\n' if quote == b'\'' => return i,
+            b if b == quote => return i + 1,
+            _ => i += 1,
+        }
+    }
+    rest.len()
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delimiters_for() {
+        assert_eq!(delimiters_for(Path::new("main.rs")), Some(("//", "")));
+        assert_eq!(delimiters_for(Path::new("script.py")), Some(("#", "")));
+        assert_eq!(delimiters_for(Path::new("config.YAML")), Some(("#", "")));
+        assert_eq!(
+            delimiters_for(Path::new("index.html")),
+            Some(("<!--", "-->"))
+        );
+        assert_eq!(delimiters_for(Path::new("style.css")), Some(("/*", "*/")));
+        assert_eq!(delimiters_for(Path::new("Makefile")), None);
+        assert_eq!(delimiters_for(Path::new("data.unknown")), None);
+    }
+
+    fn strip(source: &str, path: &str) -> String {
+        let syntax = syntax_for(Path::new(path)).unwrap();
+        String::from_utf8(strip_comments(source.as_bytes(), syntax)).unwrap()
+    }
+
+    #[test]
+    fn test_strip_line_and_block_comments() {
+        let source =
+            "// header\nlet a = 1; // trailing\n/* block\n   comment */\nlet b = /* inline */ 2;\n";
+        assert_eq!(strip(source, "main.rs"), "let a = 1;\nlet b = 2;\n");
+    }
+
+    #[test]
+    fn test_strip_keeps_strings() {
+        let source = "let url = \"http://example.com\"; // link\nlet s = \"a \\\" /* b */\";\n";
+        assert_eq!(
+            strip(source, "main.ts"),
+            "let url = \"http://example.com\";\nlet s = \"a \\\" /* b */\";\n"
+        );
+    }
+
+    #[test]
+    fn test_strip_rust_lifetimes() {
+        let source = "fn f<'a>(x: &'a str) {} // done\nlet y = 1; // more\n";
+        assert_eq!(
+            strip(source, "lib.rs"),
+            "fn f<'a>(x: &'a str) {}\nlet y = 1;\n"
+        );
+    }
+
+    #[test]
+    fn test_strip_odd_lifetimes() {
+        let source = "fn f(x: &'static str) {} // c\nlet c = '/'; // d\n";
+        assert_eq!(
+            strip(source, "lib.rs"),
+            "fn f(x: &'static str) {}\nlet c = '/';\n"
+        );
+    }
+
+    #[test]
+    fn test_strip_keeps_shebang() {
+        let source = "#!/usr/bin/env python3\n# comment\nprint(1)\n";
+        assert_eq!(
+            strip(source, "run.py"),
+            "#!/usr/bin/env python3\nprint(1)\n"
+        );
+    }
+
+    #[test]
+    fn test_strip_hash_comments() {
+        let source = "# comment\nx = '#not a comment'  # real\n";
+        assert_eq!(strip(source, "script.py"), "x = '#not a comment'\n");
+    }
+
+    #[test]
+    fn test_strip_markup_comments() {
+        let source = "<!-- note -->\n<p>don't</p>\n";
+        assert_eq!(strip(source, "page.html"), "<p>don't</p>\n");
+    }
+}
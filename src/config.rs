@@ -0,0 +1,315 @@
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory};
+use serde::Deserialize;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use concacti::{
+    Encoding, LineEndings, ListFormat, OutputFormat, SizeOrder, SortMode, Template, Traversal,
+    TreeSort,
+};
+
+use crate::transcode::OutputEncoding;
+use crate::Cli;
+
+/// Name of the file, looked up in the first input directory and then the
+/// current directory, holding default settings.
+pub const CONFIG_FILE_NAME: &str = "concacti.toml";
+
+/// Declares `Config` with an optional field for each `Cli` field, or field of
+/// its `ConcatOptions`, that can be set from the config file, and the code to
+/// copy them over.
+macro_rules! config_fields {
+    (
+        cli { $($cli_field:ident: $cli_ty:ty,)* }
+        options { $($field:ident: $ty:ty,)* }
+    ) => {
+        /// Settings from `concacti.toml`. Keys are the long flag names, e.g.
+        /// `max-depth = 2` or `patterns = ["**/*.rs"]`.
+        #[derive(Debug, Default, Deserialize)]
+        #[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+        pub struct Config {
+            $($cli_field: Option<$cli_ty>,)*
+            $($field: Option<$ty>,)*
+        }
+
+        impl Config {
+            /// Copies each setting into `cli` unless the same flag was given on the command line.
+            pub fn apply(self, cli: &mut Cli, matches: &ArgMatches) {
+                let from_command_line = |id: &str| {
+                    matches.value_source(id) == Some(ValueSource::CommandLine)
+                };
+                $(
+                    if let Some(value) = self.$cli_field {
+                        if !from_command_line(stringify!($cli_field)) {
+                            cli.$cli_field = value.into();
+                        }
+                    }
+                )*
+                $(
+                    if let Some(value) = self.$field {
+                        if !from_command_line(stringify!($field)) {
+                            cli.options.$field = value.into();
+                        }
+                    }
+                )*
+            }
+        }
+    };
+}
+
+config_fields! {
+    cli {
+        output: PathBuf,
+        gzip: bool,
+        output_encoding: OutputEncoding,
+        buffer_size: usize,
+    }
+    options {
+        patterns: Vec<String>,
+        case_insensitive: bool,
+        ext: Vec<String>,
+        no_ext: Vec<String>,
+        include_from: Vec<PathBuf>,
+        input_list_format: ListFormat,
+        since: String,
+        order_by_pattern: bool,
+        sort_by_size: SizeOrder,
+        order_file: Vec<PathBuf>,
+        strict_globs: bool,
+        limit: usize,
+        max_files_per_dir: usize,
+        max_depth: usize,
+        no_recursive: bool,
+        exclude_dir: Vec<String>,
+        prune_defaults: bool,
+        min_depth: usize,
+        write_filenames: bool,
+        write_tree: bool,
+        filtered_tree: bool,
+        tree_only: bool,
+        no_content: bool,
+        tree_sort: TreeSort,
+        tree_depth: usize,
+        tree_sizes: bool,
+        tree_ascii: bool,
+        relative_paths: bool,
+        relative_to: PathBuf,
+        checksums: bool,
+        group_by_dir: bool,
+        comment_style: String,
+        auto_comment: bool,
+        allow_output_in_tree: bool,
+        fail_fast: bool,
+        hidden: bool,
+        respect_gitignore: bool,
+        skip_binary: bool,
+        binary_as_base64: bool,
+        encoding: Encoding,
+        line_endings: LineEndings,
+        strip_comments: bool,
+        squeeze_blank: bool,
+        trim_trailing: bool,
+        tabs_to_spaces: usize,
+        spaces_to_tabs: usize,
+        ensure_trailing_newline: bool,
+        indent_by_depth: bool,
+        indent_width: usize,
+        line_numbers: bool,
+        highlight: bool,
+        jobs: usize,
+        follow_symlinks: bool,
+        format: OutputFormat,
+        chunk_lines: usize,
+        header: String,
+        header_file: PathBuf,
+        prepend_file: PathBuf,
+        append_file: PathBuf,
+        toc: bool,
+        separator: String,
+        template: Template,
+        blank_lines: usize,
+        exclude_empty: bool,
+        skip_lockfiles: bool,
+        min_file_size: u64,
+        max_file_size: u64,
+        truncate_file: usize,
+        head: usize,
+        tail: usize,
+        summary: bool,
+        quiet: bool,
+        verbose: u8,
+        count_tokens: bool,
+        max_tokens: u64,
+        max_total_size: u64,
+        sort: SortMode,
+        traversal: Traversal,
+    }
+}
+
+impl Config {
+    /// Checks values that clap would have rejected on the command line.
+    fn check_values(&self) -> Result<(), String> {
+        for (key, width) in [
+            ("tabs-to-spaces", self.tabs_to_spaces),
+            ("spaces-to-tabs", self.spaces_to_tabs),
+        ] {
+            if width == Some(0) {
+                return Err(format!("{} must be at least 1", key));
+            }
+        }
+        Ok(())
+    }
+
+    /// Makes relative paths relative to the directory holding the config file.
+    fn resolve_paths(&mut self, base: &Path) {
+        if let Some(output) = &mut self.output {
+            *output = base.join(&*output);
+        }
+        if let Some(include_from) = &mut self.include_from {
+            for path in include_from {
+                *path = base.join(&*path);
+            }
+        }
+        for file in [
+            &mut self.header_file,
+            &mut self.prepend_file,
+            &mut self.append_file,
+            &mut self.relative_to,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *file = base.join(&*file);
+        }
+    }
+}
+
+/// Looks for a config file and applies it to `cli`, so that flags given on
+/// the command line still take precedence.
+pub fn apply_config_file(cli: &mut Cli, matches: &ArgMatches) -> io::Result<()> {
+    let candidates = cli
+        .options
+        .directories
+        .first()
+        .map(|directory| directory.join(CONFIG_FILE_NAME))
+        .into_iter()
+        .chain([PathBuf::from(CONFIG_FILE_NAME)]);
+    let Some(path) = candidates.into_iter().find(|path| path.is_file()) else {
+        return Ok(());
+    };
+
+    let text = fs::read_to_string(&path)?;
+    let invalid = |e: String| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", path.display(), e),
+        )
+    };
+    let mut config = parse_config(&text).map_err(|e| invalid(e.to_string()))?;
+    config.check_values().map_err(invalid)?;
+    check_conflicts(&text, matches).map_err(invalid)?;
+    config.resolve_paths(path.parent().unwrap_or(Path::new("")));
+    config.apply(cli, matches);
+    Ok(())
+}
+
+fn parse_config(text: &str) -> Result<Config, toml::de::Error> {
+    toml::from_str(text)
+}
+
+/// Fails if a setting in the config file conflicts with another one set there
+/// or on the command line, as clap does for flags that can't be combined.
+/// Settings the command line overrides, and `false` or 0, don't count.
+fn check_conflicts(text: &str, matches: &ArgMatches) -> Result<(), String> {
+    let table: toml::Table = toml::from_str(text).map_err(|e| e.to_string())?;
+    let from_command_line = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+    let from_config: Vec<String> = table
+        .iter()
+        .filter(|(_, value)| {
+            !matches!(value, toml::Value::Boolean(false) | toml::Value::Integer(0))
+        })
+        .map(|(key, _)| key.replace('-', "_"))
+        .filter(|id| !from_command_line(id))
+        .collect();
+
+    // Conflicts are declared on one side only, so every pair is checked.
+    let in_config = |id: &str| from_config.iter().any(|set| set == id);
+    let command = Cli::command();
+    for arg in command.get_arguments() {
+        let id = arg.get_id().as_str();
+        for other in command.get_arg_conflicts_with(arg) {
+            let other = other.get_id().as_str();
+            let set = |id| in_config(id) || from_command_line(id);
+            if (in_config(id) || in_config(other)) && set(id) && set(other) {
+                return Err(format!(
+                    "{} can't be used with {}",
+                    id.replace('_', "-"),
+                    other.replace('_', "-")
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::{CommandFactory, FromArgMatches};
+
+    use super::*;
+
+    #[test]
+    fn test_command_line_overrides_config() {
+        let config = parse_config(
+            r##"
+            patterns = ["**/*.rs"]
+            max-depth = 2
+            comment-style = "#"
+            tree-sort = "files-first"
+            "##,
+        )
+        .unwrap();
+
+        let matches =
+            Cli::command().get_matches_from(["concacti", "-d", ".", "--comment-style", ";;"]);
+        let mut cli = Cli::from_arg_matches(&matches).unwrap();
+        config.apply(&mut cli, &matches);
+
+        assert_eq!(cli.options.patterns, ["**/*.rs"]);
+        assert_eq!(cli.options.max_depth, 2);
+        assert_eq!(cli.options.comment_style, ";;");
+        assert_eq!(cli.options.tree_sort, TreeSort::FilesFirst);
+    }
+
+    #[test]
+    fn test_config_conflicts() {
+        let matches = Cli::command().get_matches_from(["concacti", "-d", ".", "-v"]);
+        let error = check_conflicts("quiet = true", &matches).unwrap_err();
+        assert_eq!(error, "quiet can't be used with verbose");
+        assert!(check_conflicts("tabs-to-spaces = 2\nspaces-to-tabs = 4", &matches).is_err());
+        assert!(check_conflicts("quiet = false", &matches).is_ok());
+
+        let matches = Cli::command().get_matches_from(["concacti", "-d", ".", "--quiet"]);
+        assert!(check_conflicts("verbose = 1", &matches).is_err());
+        assert!(check_conflicts("verbose = 0", &matches).is_ok());
+    }
+
+    #[test]
+    fn test_zero_tab_width() {
+        let config = parse_config("tabs-to-spaces = 0").unwrap();
+        assert_eq!(
+            config.check_values().unwrap_err(),
+            "tabs-to-spaces must be at least 1"
+        );
+        assert!(Cli::command()
+            .try_get_matches_from(["concacti", "-d", ".", "--spaces-to-tabs", "0"])
+            .is_err());
+    }
+
+    #[test]
+    fn test_unknown_config_key() {
+        assert!(parse_config("max-dept = 2").is_err());
+    }
+}
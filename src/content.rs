@@ -0,0 +1,400 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::path::Path;
+
+/// Extensions of files whose whitespace carries meaning, such as Python
+/// indentation or Markdown line breaks, which whitespace cleanup leaves alone.
+const WHITESPACE_SENSITIVE_EXTENSIONS: &[&str] = &[
+    "py", "pyi", "yaml", "yml", "md", "markdown", "mk", "diff", "patch",
+];
+
+/// Names of files, like Makefiles, that need their tabs.
+const WHITESPACE_SENSITIVE_NAMES: &[&str] = &["Makefile", "makefile", "GNUmakefile"];
+
+/// How file contents that aren't valid UTF-8 are handled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Encoding {
+    /// Write the bytes unchanged
+    #[default]
+    Raw,
+    /// Skip files that aren't valid UTF-8
+    Utf8Strict,
+    /// Replace invalid UTF-8 sequences with U+FFFD
+    Utf8Lossy,
+}
+
+/// Line endings to write file contents with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LineEndings {
+    /// Keep each file's line endings as they are
+    #[default]
+    Preserve,
+    /// Unix line endings (\n)
+    Lf,
+    /// Windows line endings (\r\n)
+    Crlf,
+}
+
+/// A regex substitution applied to every file's contents.
+#[derive(Clone, Debug)]
+pub struct Replacement {
+    pub pattern: regex::bytes::Regex,
+    /// May refer to capture groups as `$1` or `${name}`.
+    pub replacement: String,
+}
+
+/// Applies each of `replacements` in turn, replacing every match.
+pub fn replace_all(contents: Vec<u8>, replacements: &[Replacement]) -> Vec<u8> {
+    replacements.iter().fold(contents, |contents, replacement| {
+        match replacement
+            .pattern
+            .replace_all(&contents, replacement.replacement.as_bytes())
+        {
+            Cow::Borrowed(_) => contents,
+            Cow::Owned(replaced) => replaced,
+        }
+    })
+}
+
+/// Rewrites every `\n` and `\r\n` in `contents` to the chosen line ending.
+/// Lone `\r` characters are left alone.
+pub fn normalize_line_endings(contents: Vec<u8>, endings: LineEndings) -> Vec<u8> {
+    let newline: &[u8] = match endings {
+        LineEndings::Preserve => return contents,
+        LineEndings::Lf => b"\n",
+        LineEndings::Crlf => b"\r\n",
+    };
+
+    let mut normalized = Vec::with_capacity(contents.len());
+    for line in contents.split_inclusive(|&b| b == b'\n') {
+        match line.strip_suffix(b"\n") {
+            Some(line) => {
+                normalized.extend_from_slice(line.strip_suffix(b"\r").unwrap_or(line));
+                normalized.extend_from_slice(newline);
+            }
+            None => normalized.extend_from_slice(line),
+        }
+    }
+    normalized
+}
+
+/// Replaces invalid UTF-8 sequences in `contents` with U+FFFD, reusing the
+/// buffer when it is already valid.
+pub fn to_utf8_lossy(contents: Vec<u8>) -> Vec<u8> {
+    match String::from_utf8_lossy(&contents) {
+        Cow::Borrowed(_) => contents,
+        Cow::Owned(replaced) => replaced.into_bytes(),
+    }
+}
+
+/// The first 8 hex digits of the SHA-256 of `contents`.
+pub fn short_checksum(contents: &[u8]) -> String {
+    Sha256::digest(contents)[..4]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Cuts `contents` down to at most `max` bytes, backing up to the start of a
+/// UTF-8 character rather than splitting it. Returns how many bytes were cut.
+pub fn truncate(contents: &mut Vec<u8>, max: usize) -> usize {
+    if contents.len() <= max {
+        return 0;
+    }
+    let mut end = max;
+    while end > 0 && (contents[end] & 0b1100_0000) == 0b1000_0000 {
+        end -= 1;
+    }
+    let cut = contents.len() - end;
+    contents.truncate(end);
+    cut
+}
+
+/// Splits `contents` into its first `head` lines and its last `tail` lines,
+/// with the number of lines between them. Returns `None` when that would
+/// leave nothing out.
+pub fn head_and_tail(contents: &[u8], head: usize, tail: usize) -> Option<(&[u8], usize, &[u8])> {
+    // Where each line starts, followed by the end of the contents.
+    let mut starts: Vec<usize> = std::iter::once(0)
+        .chain(
+            contents
+                .iter()
+                .enumerate()
+                .filter(|&(_, &b)| b == b'\n')
+                .map(|(index, _)| index + 1)
+                .filter(|&start| start < contents.len()),
+        )
+        .collect();
+    let lines = if contents.is_empty() { 0 } else { starts.len() };
+    starts.truncate(lines);
+    starts.push(contents.len());
+    if head + tail >= lines {
+        return None;
+    }
+    let head_end = starts[head];
+    let tail_start = starts[lines - tail];
+    Some((
+        &contents[..head_end],
+        lines - head - tail,
+        &contents[tail_start..],
+    ))
+}
+
+/// Prefixes every line of `contents` with its right-aligned line number.
+/// The number column is as wide as the largest line number in the file.
+pub fn number_lines(contents: &[u8]) -> Vec<u8> {
+    let lines: Vec<&[u8]> = contents.split_inclusive(|&b| b == b'\n').collect();
+    let width = lines.len().to_string().len();
+
+    let mut numbered = Vec::with_capacity(contents.len() + lines.len() * (width + 3));
+    for (index, line) in lines.iter().enumerate() {
+        numbered.extend_from_slice(format!("{:>width$} | ", index + 1).as_bytes());
+        numbered.extend_from_slice(line);
+    }
+    numbered
+}
+
+/// Prefixes every non-blank line of `contents` with `width` spaces.
+pub fn indent_lines(contents: &[u8], width: usize) -> Vec<u8> {
+    let mut indented = Vec::with_capacity(contents.len());
+    for line in contents.split_inclusive(|&b| b == b'\n') {
+        if line != b"\n" && line != b"\r\n" {
+            indented.resize(indented.len() + width, b' ');
+        }
+        indented.extend_from_slice(line);
+    }
+    indented
+}
+
+/// Collapses runs of consecutive empty lines into a single empty line, like `cat -s`.
+pub fn squeeze_blank(contents: &[u8]) -> Vec<u8> {
+    let mut squeezed = Vec::with_capacity(contents.len());
+    let mut previous_blank = false;
+
+    for line in contents.split_inclusive(|&b| b == b'\n') {
+        let blank = line == b"\n" || line == b"\r\n";
+        if !(blank && previous_blank) {
+            squeezed.extend_from_slice(line);
+        }
+        previous_blank = blank;
+    }
+    squeezed
+}
+
+/// Whether changing the whitespace of the file at `path` could change what it means.
+pub fn whitespace_sensitive(path: &Path) -> bool {
+    let ext = path.extension().and_then(|ext| ext.to_str());
+    let name = path.file_name().and_then(|name| name.to_str());
+    ext.is_some_and(|ext| {
+        WHITESPACE_SENSITIVE_EXTENSIONS
+            .iter()
+            .any(|known| known.eq_ignore_ascii_case(ext))
+    }) || name.is_some_and(|name| WHITESPACE_SENSITIVE_NAMES.contains(&name))
+}
+
+/// Splits `line` into its text and its `\n` or `\r\n` ending.
+fn split_line_ending(line: &[u8]) -> (&[u8], &[u8]) {
+    let ending = if line.ends_with(b"\r\n") {
+        2
+    } else if line.ends_with(b"\n") {
+        1
+    } else {
+        0
+    };
+    line.split_at(line.len() - ending)
+}
+
+/// Removes spaces and tabs from the end of every line.
+pub fn trim_trailing(contents: &[u8]) -> Vec<u8> {
+    let mut trimmed = Vec::with_capacity(contents.len());
+    for line in contents.split_inclusive(|&b| b == b'\n') {
+        let (text, ending) = split_line_ending(line);
+        let end = text
+            .iter()
+            .rposition(|&b| b != b' ' && b != b'\t')
+            .map_or(0, |i| i + 1);
+        trimmed.extend_from_slice(&text[..end]);
+        trimmed.extend_from_slice(ending);
+    }
+    trimmed
+}
+
+/// Rewrites the indentation of every line, made of tabs to stops every
+/// `width` columns and spaces, as tabs and spaces, or as spaces only.
+fn reindent(contents: &[u8], width: usize, use_tabs: bool) -> Vec<u8> {
+    // The flags reject 0; a tab is never less than a column wide.
+    let width = width.max(1);
+    let mut reindented = Vec::with_capacity(contents.len());
+    for line in contents.split_inclusive(|&b| b == b'\n') {
+        let indent_len = line
+            .iter()
+            .position(|&b| b != b' ' && b != b'\t')
+            .unwrap_or(line.len());
+        let columns = line[..indent_len].iter().fold(0, |column, &b| match b {
+            b'\t' => (column / width + 1) * width,
+            _ => column + 1,
+        });
+        let (tabs, spaces) = if use_tabs {
+            (columns / width, columns % width)
+        } else {
+            (0, columns)
+        };
+        reindented.resize(reindented.len() + tabs, b'\t');
+        reindented.resize(reindented.len() + spaces, b' ');
+        reindented.extend_from_slice(&line[indent_len..]);
+    }
+    reindented
+}
+
+/// Expands tabs in the indentation of every line to stops every `width` columns.
+pub fn tabs_to_spaces(contents: &[u8], width: usize) -> Vec<u8> {
+    reindent(contents, width, false)
+}
+
+/// Replaces each `width` columns of indentation on every line with a tab.
+pub fn spaces_to_tabs(contents: &[u8], width: usize) -> Vec<u8> {
+    reindent(contents, width, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_line_endings() {
+        let mixed = b"a\r\nb\nc\rd\r\n".to_vec();
+        assert_eq!(
+            normalize_line_endings(mixed.clone(), LineEndings::Lf),
+            b"a\nb\nc\rd\n"
+        );
+        assert_eq!(
+            normalize_line_endings(mixed.clone(), LineEndings::Crlf),
+            b"a\r\nb\r\nc\rd\r\n"
+        );
+        assert_eq!(
+            normalize_line_endings(mixed.clone(), LineEndings::Preserve),
+            mixed
+        );
+        assert_eq!(
+            normalize_line_endings(b"x".to_vec(), LineEndings::Crlf),
+            b"x"
+        );
+    }
+
+    #[test]
+    fn test_replace_all() {
+        let replacements = [
+            Replacement {
+                pattern: regex::bytes::Regex::new(r#"api_key="[^"]*""#).unwrap(),
+                replacement: r#"api_key="REDACTED""#.to_string(),
+            },
+            Replacement {
+                pattern: regex::bytes::Regex::new(r"/home/(\w+)").unwrap(),
+                replacement: "~$1".to_string(),
+            },
+        ];
+        assert_eq!(
+            replace_all(br#"api_key="s3cr3t" at /home/me"#.to_vec(), &replacements),
+            br#"api_key="REDACTED" at ~me"#
+        );
+        assert_eq!(replace_all(b"plain".to_vec(), &replacements), b"plain");
+    }
+
+    #[test]
+    fn test_short_checksum() {
+        assert_eq!(short_checksum(b""), "e3b0c442");
+        assert_eq!(short_checksum(b"hello\n"), "5891b5b5");
+    }
+
+    #[test]
+    fn test_truncate() {
+        let mut contents = b"short".to_vec();
+        assert_eq!(truncate(&mut contents, 10), 0);
+        assert_eq!(contents, b"short");
+
+        // "é" is two bytes; cutting after its first byte keeps neither.
+        let mut contents = "abé!".as_bytes().to_vec();
+        assert_eq!(truncate(&mut contents, 3), 3);
+        assert_eq!(contents, b"ab");
+    }
+
+    #[test]
+    fn test_head_and_tail() {
+        let contents = b"1\n2\n3\n4\n5";
+        assert_eq!(
+            head_and_tail(contents, 1, 2),
+            Some((&b"1\n"[..], 2, &b"4\n5"[..]))
+        );
+        assert_eq!(
+            head_and_tail(contents, 2, 0),
+            Some((&b"1\n2\n"[..], 3, &b""[..]))
+        );
+        assert_eq!(
+            head_and_tail(contents, 0, 1),
+            Some((&b""[..], 4, &b"5"[..]))
+        );
+        assert_eq!(head_and_tail(contents, 3, 2), None);
+        assert_eq!(
+            head_and_tail(b"1\n2\n", 1, 0),
+            Some((&b"1\n"[..], 1, &b""[..]))
+        );
+        assert_eq!(head_and_tail(b"", 0, 0), None);
+    }
+
+    #[test]
+    fn test_number_lines() {
+        assert_eq!(number_lines(b"a\nb\n"), b"1 | a\n2 | b\n");
+    }
+
+    #[test]
+    fn test_number_lines_without_trailing_newline() {
+        assert_eq!(number_lines(b"a\nb"), b"1 | a\n2 | b");
+    }
+
+    #[test]
+    fn test_number_lines_width() {
+        let contents = "x\n".repeat(10);
+        let numbered = String::from_utf8(number_lines(contents.as_bytes())).unwrap();
+        assert!(numbered.starts_with(" 1 | x\n"));
+        assert!(numbered.ends_with("10 | x\n"));
+    }
+
+    #[test]
+    fn test_number_lines_empty() {
+        assert!(number_lines(b"").is_empty());
+    }
+
+    #[test]
+    fn test_to_utf8_lossy() {
+        assert_eq!(to_utf8_lossy(b"valid".to_vec()), b"valid");
+        assert_eq!(to_utf8_lossy(b"a\xffb".to_vec()), "a\u{FFFD}b".as_bytes());
+    }
+
+    #[test]
+    fn test_indent_lines() {
+        assert_eq!(indent_lines(b"a\n\n  b", 2), b"  a\n\n    b");
+        assert_eq!(indent_lines(b"a\n", 0), b"a\n");
+    }
+
+    #[test]
+    fn test_whitespace_cleanup() {
+        assert_eq!(trim_trailing(b"a  \r\n\t\nb \t"), b"a\r\n\nb");
+        assert_eq!(tabs_to_spaces(b"\tx\n \ty\tz\n", 4), b"    x\n    y\tz\n");
+        assert_eq!(spaces_to_tabs(b"      x\n\t  y\n", 4), b"\t  x\n\t  y\n");
+        assert!(whitespace_sensitive(Path::new("src/app.py")));
+        assert!(whitespace_sensitive(Path::new("Makefile")));
+        assert!(!whitespace_sensitive(Path::new("src/main.rs")));
+    }
+
+    #[test]
+    fn test_squeeze_blank() {
+        assert_eq!(squeeze_blank(b"a\n\n\n\nb\n"), b"a\n\nb\n");
+        assert_eq!(squeeze_blank(b"\n\n\na\n\n"), b"\na\n\n");
+        assert_eq!(squeeze_blank(b"a\r\n\r\n\r\nb"), b"a\r\n\r\nb");
+        assert_eq!(squeeze_blank(b"a\n  \n  \nb"), b"a\n  \n  \nb");
+    }
+}
@@ -0,0 +1,56 @@
+use std::io::{self, Write};
+
+/// Wraps a writer and counts the bytes and lines that pass through it.
+pub struct CountingWriter<W> {
+    inner: W,
+    count: u64,
+    lines: u64,
+}
+
+impl<W: Write> CountingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        CountingWriter {
+            inner,
+            count: 0,
+            lines: 0,
+        }
+    }
+
+    /// Total bytes written so far.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Newlines written so far.
+    pub fn lines(&self) -> u64 {
+        self.lines
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written as u64;
+        self.lines += buf[..written].iter().filter(|&&b| b == b'\n').count() as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counting_writer() {
+        let mut writer = CountingWriter::new(Vec::new());
+        writer.write_all(b"hello").unwrap();
+        writeln!(writer, " world").unwrap();
+        assert_eq!(writer.count(), 12);
+        assert_eq!(writer.lines(), 1);
+        assert_eq!(writer.inner, b"hello world\n");
+    }
+}
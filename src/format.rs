@@ -0,0 +1,171 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::io::{self, Write};
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    /// Raw contents, optionally preceded by filename comments
+    #[default]
+    Plain,
+    /// A `###` heading and fenced code block per file
+    Markdown,
+    /// A JSON object with a `files` array of paths, sizes and contents
+    Json,
+    /// An XML document with a `<file>` element, its contents in CDATA, per file
+    Xml,
+}
+
+impl OutputFormat {
+    /// File extension for output written in this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Plain => "txt",
+            OutputFormat::Markdown => "md",
+            OutputFormat::Json => "json",
+            OutputFormat::Xml => "xml",
+        }
+    }
+
+    /// Whether the output is a single document that tools parse, rather than
+    /// text with the header, separators and table of contents mixed in.
+    pub fn is_structured(self) -> bool {
+        matches!(self, OutputFormat::Json | OutputFormat::Xml)
+    }
+}
+
+/// Maps file extensions to the language tag used on Markdown code fences.
+const LANGUAGES: &[(&str, &str)] = &[
+    ("rs", "rust"),
+    ("ts", "ts"),
+    ("tsx", "tsx"),
+    ("js", "js"),
+    ("jsx", "jsx"),
+    ("py", "python"),
+    ("rb", "ruby"),
+    ("go", "go"),
+    ("java", "java"),
+    ("kt", "kotlin"),
+    ("swift", "swift"),
+    ("c", "c"),
+    ("h", "c"),
+    ("cpp", "cpp"),
+    ("hpp", "cpp"),
+    ("cs", "csharp"),
+    ("sh", "bash"),
+    ("json", "json"),
+    ("toml", "toml"),
+    ("yaml", "yaml"),
+    ("yml", "yaml"),
+    ("md", "markdown"),
+    ("html", "html"),
+    ("css", "css"),
+    ("sql", "sql"),
+];
+
+/// Returns the fence language for `path`, or an empty string if unknown.
+pub fn language_for(path: &Path) -> &'static str {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(|ext| {
+            LANGUAGES
+                .iter()
+                .find(|(known, _)| known.eq_ignore_ascii_case(ext))
+        })
+        .map_or("", |(_, language)| language)
+}
+
+/// Returns a backtick fence longer than any backtick run inside `contents`,
+/// so the file can't close its own code block.
+pub fn fence_for(contents: &[u8]) -> String {
+    let longest_run = contents
+        .split(|&b| b != b'`')
+        .map(|run| run.len())
+        .max()
+        .unwrap_or(0);
+    "`".repeat(longest_run.max(2) + 1)
+}
+
+/// Writes a heading and fenced code block for the file. With `chunk_lines`,
+/// longer files are split into several blocks of at most that many lines,
+/// each headed with its part number.
+pub fn write_markdown_file<W: Write>(
+    writer: &mut W,
+    path: &Path,
+    contents: &[u8],
+    chunk_lines: Option<usize>,
+) -> io::Result<()> {
+    let fence = fence_for(contents);
+    let lines: Vec<&[u8]> = contents.split_inclusive(|&b| b == b'\n').collect();
+    let chunks: Vec<&[&[u8]]> = match chunk_lines.filter(|&n| n > 0) {
+        Some(n) if lines.len() > n => lines.chunks(n).collect(),
+        _ => vec![&lines[..]],
+    };
+
+    for (index, chunk) in chunks.iter().enumerate() {
+        if chunks.len() > 1 {
+            writeln!(
+                writer,
+                "### {} (part {}/{})",
+                path.display(),
+                index + 1,
+                chunks.len()
+            )?;
+        } else {
+            writeln!(writer, "### {}", path.display())?;
+        }
+        writeln!(writer)?;
+        writeln!(writer, "{}{}", fence, language_for(path))?;
+        for line in chunk.iter() {
+            writer.write_all(line)?;
+        }
+        if chunk.last().is_some_and(|line| !line.ends_with(b"\n")) {
+            writeln!(writer)?;
+        }
+        writeln!(writer, "{}", fence)?;
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_language_for() {
+        assert_eq!(language_for(Path::new("src/main.rs")), "rust");
+        assert_eq!(language_for(Path::new("app.TS")), "ts");
+        assert_eq!(language_for(Path::new("Makefile")), "");
+        assert_eq!(language_for(Path::new("data.unknown")), "");
+    }
+
+    #[test]
+    fn test_fence_for() {
+        assert_eq!(fence_for(b"plain text"), "```");
+        assert_eq!(fence_for(b"a ``` b"), "````");
+        assert_eq!(fence_for(b"`````"), "``````");
+    }
+
+    #[test]
+    fn test_write_markdown_file() {
+        let mut output = Vec::new();
+        write_markdown_file(&mut output, Path::new("lib.rs"), b"fn a() {}", None).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "### lib.rs\n\n```rust\nfn a() {}\n```\n\n"
+        );
+    }
+
+    #[test]
+    fn test_write_markdown_file_in_chunks() {
+        let mut output = Vec::new();
+        write_markdown_file(&mut output, Path::new("a.py"), b"1\n2\n3", Some(2)).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "### a.py (part 1/2)\n\n```python\n1\n2\n```\n\n\
+             ### a.py (part 2/2)\n\n```python\n3\n```\n\n"
+        );
+    }
+}
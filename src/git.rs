@@ -0,0 +1,78 @@
+use std::collections::HashSet;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Returns the files under `directory` that differ from `reference` according
+/// to `git diff`, joined onto `directory` so they compare equal to walked paths.
+/// Deleted files are listed too; they simply never turn up in a walk.
+pub fn changed_files(directory: &Path, reference: &str) -> io::Result<HashSet<PathBuf>> {
+    let output = Command::new("git")
+        .args(["diff", "--name-only", "--relative", "-z", reference, "--"])
+        .current_dir(directory)
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "git diff {} failed in {}: {}",
+            reference,
+            directory.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    Ok(output
+        .stdout
+        .split(|&b| b == 0)
+        .filter(|name| !name.is_empty())
+        .map(|name| directory.join(String::from_utf8_lossy(name).as_ref()))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn git(directory: &Path, args: &[&str]) {
+        let status = Command::new("git")
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(directory)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "git {:?} failed", args);
+    }
+
+    #[test]
+    fn test_changed_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir(root.join("src")).unwrap();
+        fs::write(root.join("src").join("changed.rs"), "old").unwrap();
+        fs::write(root.join("src").join("same.rs"), "same").unwrap();
+        fs::write(root.join("deleted.rs"), "gone").unwrap();
+        git(root, &["init", "-q"]);
+        git(root, &["add", "."]);
+        git(root, &["commit", "-q", "-m", "initial"]);
+
+        fs::write(root.join("src").join("changed.rs"), "new").unwrap();
+        fs::remove_file(root.join("deleted.rs")).unwrap();
+
+        let changed = changed_files(root, "HEAD").unwrap();
+        assert!(changed.contains(&root.join("src").join("changed.rs")));
+        assert!(changed.contains(&root.join("deleted.rs")));
+        assert!(!changed.contains(&root.join("src").join("same.rs")));
+
+        // Paths are relative to the directory given, not the repository root.
+        let src = root.join("src");
+        assert_eq!(
+            changed_files(&src, "HEAD").unwrap(),
+            HashSet::from([src.join("changed.rs")])
+        );
+
+        assert!(changed_files(root, "no-such-ref").is_err());
+    }
+}
@@ -0,0 +1,97 @@
+use ignore::gitignore::{Gitignore, GitignoreBuilder, Glob};
+use ignore::Match;
+use std::io::{self};
+use std::path::Path;
+
+/// Stack of `.gitignore` matchers, one per directory currently being
+/// traversed, so that nested ignore files only apply to their own subtree.
+#[derive(Clone, Default)]
+pub struct GitignoreStack {
+    matchers: Vec<Gitignore>,
+}
+
+impl GitignoreStack {
+    /// Loads `dir/.gitignore` onto the stack if present. Returns whether a
+    /// matcher was pushed, in which case the caller must `pop` it afterwards.
+    pub fn push_dir(&mut self, dir: &Path) -> io::Result<bool> {
+        let path = dir.join(".gitignore");
+        if !path.is_file() {
+            return Ok(false);
+        }
+
+        let mut builder = GitignoreBuilder::new(dir);
+        if let Some(e) = builder.add(&path) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+        }
+        let matcher = builder
+            .build()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        self.matchers.push(matcher);
+        Ok(true)
+    }
+
+    pub fn pop(&mut self) {
+        self.matchers.pop();
+    }
+
+    /// Returns the rule ignoring `path`, if any. Checks the innermost
+    /// `.gitignore` first, falling back to outer ones, so that deeper rules
+    /// (including `!` whitelists) win.
+    pub fn ignoring_rule(&self, path: &Path, is_dir: bool) -> Option<&Glob> {
+        for matcher in self.matchers.iter().rev() {
+            match matcher.matched(path, is_dir) {
+                Match::None => continue,
+                Match::Ignore(glob) => return Some(glob),
+                Match::Whitelist(_) => return None,
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_no_gitignore() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut stack = GitignoreStack::default();
+
+        assert!(!stack.push_dir(temp_dir.path()).unwrap());
+        assert!(stack
+            .ignoring_rule(&temp_dir.path().join("file.txt"), false)
+            .is_none());
+    }
+
+    #[test]
+    fn test_nested_gitignore_scope() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path();
+        fs::create_dir(path.join("subdir")).unwrap();
+        fs::write(path.join(".gitignore"), "target/\n").unwrap();
+        fs::write(path.join("subdir").join(".gitignore"), "*.log\n!keep.log\n").unwrap();
+
+        let mut stack = GitignoreStack::default();
+        assert!(stack.push_dir(path).unwrap());
+        assert!(stack.ignoring_rule(&path.join("target"), true).is_some());
+        assert!(stack.ignoring_rule(&path.join("target"), false).is_none());
+        assert!(stack.ignoring_rule(&path.join("root.log"), false).is_none());
+
+        assert!(stack.push_dir(&path.join("subdir")).unwrap());
+        let rule = stack.ignoring_rule(&path.join("subdir").join("debug.log"), false);
+        assert_eq!(rule.unwrap().original(), "*.log");
+        assert!(stack
+            .ignoring_rule(&path.join("subdir").join("keep.log"), false)
+            .is_none());
+
+        stack.pop();
+        assert!(stack
+            .ignoring_rule(&path.join("subdir").join("debug.log"), false)
+            .is_none());
+    }
+}
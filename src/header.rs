@@ -0,0 +1,79 @@
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Values substituted for the `{...}` tokens in a header template.
+pub struct HeaderValues<'a> {
+    /// Today's date as `YYYY-MM-DD`, for `{date}`.
+    pub date: String,
+    /// The input directories, for `{dir}`.
+    pub directories: &'a [PathBuf],
+    /// Number of files selected for the output, for `{file_count}`.
+    pub file_count: usize,
+}
+
+/// Replaces `{date}`, `{dir}` and `{file_count}` in `template`. Other text,
+/// including unknown tokens, is left as is.
+pub fn render_header(template: &str, values: &HeaderValues) -> String {
+    let directories = values
+        .directories
+        .iter()
+        .map(|directory| directory.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    template
+        .replace("{date}", &values.date)
+        .replace("{dir}", &directories)
+        .replace("{file_count}", &values.file_count.to_string())
+}
+
+/// Current UTC date as `YYYY-MM-DD`.
+pub fn today() -> String {
+    let days = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() / 86400);
+    let (year, month, day) = civil_from_days(days as i64);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Converts days since 1970-01-01 to a proleptic Gregorian `(year, month, day)`,
+/// using Howard Hinnant's `civil_from_days` algorithm.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_header() {
+        let directories = [PathBuf::from("src"), PathBuf::from("docs")];
+        let values = HeaderValues {
+            date: "2024-03-01".to_string(),
+            directories: &directories,
+            file_count: 12,
+        };
+        assert_eq!(
+            render_header("# {dir} on {date}: {file_count} files {unknown}", &values),
+            "# src, docs on 2024-03-01: 12 files {unknown}"
+        );
+    }
+
+    #[test]
+    fn test_civil_from_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(19782), (2024, 2, 29));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+    }
+}
@@ -0,0 +1,72 @@
+use std::io;
+use std::path::Path;
+
+/// Colors `contents` with ANSI escapes for the language of `path`. Returns
+/// `None` for files without a known source language or that aren't UTF-8,
+/// which are written as they are.
+#[cfg(feature = "highlight")]
+pub fn highlight(path: &Path, contents: &[u8]) -> Option<Vec<u8>> {
+    use std::sync::OnceLock;
+    use syntect::easy::HighlightLines;
+    use syntect::highlighting::{Theme, ThemeSet};
+    use syntect::parsing::SyntaxSet;
+    use syntect::util::{as_24_bit_terminal_escaped, LinesWithEndings};
+
+    static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+    static THEME: OnceLock<Theme> = OnceLock::new();
+
+    let syntaxes = SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines);
+    let extension = path.extension()?.to_str()?;
+    let syntax = syntaxes
+        .find_syntax_by_extension(extension)
+        .filter(|syntax| syntax.name != "Plain Text")?;
+    let text = std::str::from_utf8(contents).ok()?;
+    let theme = THEME.get_or_init(|| {
+        let mut themes = ThemeSet::load_defaults().themes;
+        themes.remove("base16-ocean.dark").unwrap_or_default()
+    });
+
+    let mut highlighter = HighlightLines::new(syntax, theme);
+    let mut highlighted = String::with_capacity(contents.len() * 2);
+    for line in LinesWithEndings::from(text) {
+        let ranges = highlighter.highlight_line(line, syntaxes).ok()?;
+        highlighted.push_str(&as_24_bit_terminal_escaped(&ranges, false));
+    }
+    // Reset the colors so they don't leak into the filename comments.
+    highlighted.push_str("\x1b[0m");
+    Some(highlighted.into_bytes())
+}
+
+#[cfg(not(feature = "highlight"))]
+pub fn highlight(_path: &Path, _contents: &[u8]) -> Option<Vec<u8>> {
+    None
+}
+
+/// Fails if `--highlight` was asked for but isn't compiled in.
+pub fn check_supported() -> io::Result<()> {
+    if cfg!(feature = "highlight") {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "concacti was built without highlighting support (enable the `highlight` feature)",
+        ))
+    }
+}
+
+#[cfg(all(test, feature = "highlight"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_highlight() {
+        let highlighted = highlight(Path::new("main.rs"), b"fn main() {}\n").unwrap();
+        let highlighted = String::from_utf8(highlighted).unwrap();
+        assert!(highlighted.contains("\x1b[38;2;"));
+        assert!(highlighted.contains("main"));
+
+        assert!(highlight(Path::new("notes.txt"), b"plain\n").is_none());
+        assert!(highlight(Path::new("Makefile.unknown"), b"all:\n").is_none());
+        assert!(highlight(Path::new("main.rs"), b"\xff\n").is_none());
+    }
+}
@@ -0,0 +1,165 @@
+use serde::Serialize;
+use std::borrow::Cow;
+use std::io::{self, Write};
+use std::path::Path;
+use termtree::Tree;
+
+/// One entry of the `files` array in `--format json` output.
+#[derive(Debug, Serialize)]
+pub struct JsonFile<'a> {
+    pub path: Cow<'a, str>,
+    /// Size of the file on disk, in bytes.
+    pub size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<Cow<'a, str>>,
+    /// Why the file was left out, if it was.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skipped: Option<&'a str>,
+}
+
+impl<'a> JsonFile<'a> {
+    /// Invalid UTF-8 in the path or contents is replaced rather than rejected.
+    pub fn included(path: &'a Path, size: u64, contents: &'a [u8]) -> Self {
+        JsonFile {
+            path: path.to_string_lossy(),
+            size,
+            content: Some(String::from_utf8_lossy(contents)),
+            skipped: None,
+        }
+    }
+
+    pub fn skipped(path: &'a Path, size: u64, note: &'a str) -> Self {
+        JsonFile {
+            path: path.to_string_lossy(),
+            size,
+            content: None,
+            skipped: Some(note),
+        }
+    }
+}
+
+/// A directory tree node, as `{"name": ..., "children": [...]}`.
+#[derive(Debug, Serialize)]
+pub struct JsonTree {
+    pub name: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<JsonTree>,
+}
+
+impl From<Tree<String>> for JsonTree {
+    fn from(tree: Tree<String>) -> Self {
+        JsonTree {
+            name: tree.root,
+            children: tree.leaves.into_iter().map(JsonTree::from).collect(),
+        }
+    }
+}
+
+/// Opens the top-level object, including the `prepend` text, the rendered
+/// `header` and a `tree` array with one entry per input directory if given,
+/// and the `files` array.
+pub fn write_start<W: Write>(
+    writer: &mut W,
+    prepend: Option<&str>,
+    header: Option<&str>,
+    trees: Option<Vec<JsonTree>>,
+) -> io::Result<()> {
+    writeln!(writer, "{{")?;
+    if let Some(prepend) = prepend {
+        write!(writer, "  \"prepend\": ")?;
+        serde_json::to_writer(&mut *writer, prepend)?;
+        writeln!(writer, ",")?;
+    }
+    if let Some(header) = header {
+        write!(writer, "  \"header\": ")?;
+        serde_json::to_writer(&mut *writer, header)?;
+        writeln!(writer, ",")?;
+    }
+    if let Some(trees) = trees {
+        write!(writer, "  \"tree\": ")?;
+        serde_json::to_writer(&mut *writer, &trees)?;
+        writeln!(writer, ",")?;
+    }
+    write!(writer, "  \"files\": [")
+}
+
+/// Writes one element of the `files` array; every element but the first is
+/// preceded by a comma.
+pub fn write_file<W: Write>(writer: &mut W, first: bool, file: &JsonFile) -> io::Result<()> {
+    if !first {
+        write!(writer, ",")?;
+    }
+    write!(writer, "\n    ")?;
+    serde_json::to_writer(&mut *writer, file)?;
+    Ok(())
+}
+
+/// Closes the `files` array and the top-level object, adding `summary` and
+/// the `append` text if given.
+pub fn write_end<W: Write, S: Serialize>(
+    writer: &mut W,
+    summary: Option<&S>,
+    append: Option<&str>,
+) -> io::Result<()> {
+    write!(writer, "\n  ]")?;
+    if let Some(summary) = summary {
+        write!(writer, ",\n  \"summary\": ")?;
+        serde_json::to_writer(&mut *writer, summary)?;
+    }
+    if let Some(append) = append {
+        write!(writer, ",\n  \"append\": ")?;
+        serde_json::to_writer(&mut *writer, append)?;
+    }
+    writeln!(writer, "\n}}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_document() {
+        let mut tree = Tree::new("root".to_string());
+        tree.push(Tree::new("a.txt".to_string()));
+
+        let mut output = Vec::new();
+        write_start(
+            &mut output,
+            Some("Review this code."),
+            Some("# Project"),
+            Some(vec![JsonTree::from(tree)]),
+        )
+        .unwrap();
+        let contents = b"line \"one\"\n\ttab \\ slash\n";
+        let file = JsonFile::included(Path::new("root/a.txt"), 24, contents);
+        write_file(&mut output, true, &file).unwrap();
+        let file = JsonFile::skipped(Path::new("root/b.bin"), 3, "skipped binary file");
+        write_file(&mut output, false, &file).unwrap();
+        write_end::<_, ()>(&mut output, None, Some("Thanks!\n")).unwrap();
+
+        let value: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(value["prepend"], "Review this code.");
+        assert_eq!(value["append"], "Thanks!\n");
+        assert_eq!(value["header"], "# Project");
+        assert_eq!(value["tree"][0]["name"], "root");
+        assert_eq!(value["tree"][0]["children"][0]["name"], "a.txt");
+        assert_eq!(value["files"][0]["path"], "root/a.txt");
+        assert_eq!(value["files"][0]["size"], 24);
+        assert_eq!(
+            value["files"][0]["content"],
+            "line \"one\"\n\ttab \\ slash\n"
+        );
+        assert_eq!(value["files"][1]["skipped"], "skipped binary file");
+        assert!(value["files"][1].get("content").is_none());
+    }
+
+    #[test]
+    fn test_json_document_without_files() {
+        let mut output = Vec::new();
+        write_start(&mut output, None, None, None).unwrap();
+        write_end::<_, ()>(&mut output, None, None).unwrap();
+
+        let value: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(value, serde_json::json!({ "files": [] }));
+    }
+}
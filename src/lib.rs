@@ -0,0 +1,4392 @@
+//! Concatenates the files in one or more directories into a single stream,
+//! optionally preceded by a directory tree.
+//!
+//! Build a [`ConcatOptions`], wrap it in a [`Concatenator`] and write the
+//! result to any [`Write`]r:
+//!
+//! ```no_run
+//! use clap::Parser;
+//! use concacti::{ConcatOptions, Concatenator};
+//!
+//! let options = ConcatOptions {
+//!     patterns: vec!["**/*.rs".to_string()],
+//!     ..ConcatOptions::parse_from(["concacti", "-d", "src"])
+//! };
+//! let stats = Concatenator::new(options).concatenate(std::io::stdout().lock())?;
+//! eprintln!("{}", stats);
+//! # Ok::<(), std::io::Error>(())
+//! ```
+
+use clap::builder::RangedU64ValueParser;
+use clap::{ArgAction, Parser, ValueEnum};
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::fs::{self, DirEntry, File};
+use std::io::{self, BufReader, Read, Write};
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+mod binary;
+mod comments;
+mod content;
+mod counting;
+mod format;
+mod git;
+mod gitignore;
+mod header;
+mod highlight;
+mod json;
+mod progress;
+mod sort;
+mod template;
+mod tokens;
+mod tree;
+mod xml;
+
+pub use content::{Encoding, LineEndings, Replacement};
+pub use format::OutputFormat;
+pub use sort::{SizeOrder, SortMode, Traversal};
+pub use template::Template;
+pub use tree::{human_size, TreeSort};
+
+use counting::CountingWriter;
+use gitignore::GitignoreStack;
+
+/// Which files to concatenate and how to write them. These are the
+/// command-line options of the `concacti` binary, so the defaults can be
+/// taken from [`ConcatOptions::parse_from`].
+#[derive(Parser, Clone, Debug)]
+pub struct ConcatOptions {
+    /// Sets the input directory to use (repeat to concatenate several directories)
+    #[arg(
+        short = 'd',
+        long = "directory",
+        value_name = "DIR",
+        required_unless_present = "files_from"
+    )]
+    pub directories: Vec<PathBuf>,
+
+    /// Concatenate exactly the files listed in FILE (use - for stdin), instead of walking directories
+    #[arg(long, value_name = "FILE")]
+    pub files_from: Option<PathBuf>,
+
+    /// How the --files-from list is written
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t = ListFormat::Lines)]
+    pub input_list_format: ListFormat,
+
+    /// Only include files that changed since the git REF (as listed by `git diff --name-only REF`)
+    #[arg(long, value_name = "REF", conflicts_with = "files_from")]
+    pub since: Option<String>,
+
+    /// File patterns to include or exclude (use ! for exclusion), matched relative to the input directory, comma-separated; {a,b} matches either alternative
+    #[arg(short, long)]
+    pub patterns: Vec<String>,
+
+    /// Match patterns without regard to case, so **/*.ts also matches FILE.TS
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub case_insensitive: bool,
+
+    /// Only include files with these extensions, comma-separated (e.g. ts,tsx)
+    #[arg(long, value_name = "LIST", value_delimiter = ',')]
+    pub ext: Vec<String>,
+
+    /// Exclude files with these extensions, comma-separated
+    #[arg(long, value_name = "LIST", value_delimiter = ',')]
+    pub no_ext: Vec<String>,
+
+    /// Read additional patterns from a file, one per line (# starts a comment)
+    #[arg(long, value_name = "FILE")]
+    pub include_from: Vec<PathBuf>,
+
+    /// Only include files whose contents match REGEX
+    #[arg(long, value_name = "REGEX")]
+    pub grep: Option<regex::bytes::Regex>,
+
+    /// With --grep, only include files whose contents don't match
+    #[arg(long, action = ArgAction::SetTrue, requires = "grep")]
+    pub grep_invert: bool,
+
+    /// Write the files matching the first include pattern, then those matching the second, and so on
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub order_by_pattern: bool,
+
+    /// Order all matched files by size rather than by directory, e.g. to fit the most files under --max-total-size
+    #[arg(
+        long,
+        value_enum,
+        value_name = "ORDER",
+        conflicts_with = "order_by_pattern"
+    )]
+    pub sort_by_size: Option<SizeOrder>,
+
+    /// Write this file, relative to its input directory, before all others; repeat to pin several in order
+    #[arg(long, value_name = "PATH")]
+    pub order_file: Vec<PathBuf>,
+
+    /// Fail instead of warning when an include pattern matches no files
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub strict_globs: bool,
+
+    /// Stop after selecting N files, e.g. for a quick preview
+    #[arg(long, value_name = "N")]
+    pub limit: Option<usize>,
+
+    /// Take at most N matching files from each directory, the first in sort order, to sample large trees
+    #[arg(long, value_name = "N")]
+    pub max_files_per_dir: Option<usize>,
+
+    /// Maximum depth for recursive search: 0 only includes files directly in
+    /// the input directory, 1 adds its subdirectories, and so on
+    #[arg(long, default_value_t = usize::MAX)]
+    pub max_depth: usize,
+
+    /// Only include files directly in the input directories, without descending into subdirectories
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub no_recursive: bool,
+
+    /// Never descend into directories with these names, comma-separated (e.g. node_modules,target)
+    #[arg(long, value_name = "NAME", value_delimiter = ',')]
+    pub exclude_dir: Vec<String>,
+
+    /// Also skip common dependency, build and cache directories such as node_modules and target
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub prune_defaults: bool,
+
+    /// Minimum depth of included files: 1 skips files directly in the input directory
+    #[arg(long, default_value_t = 0)]
+    pub min_depth: usize,
+
+    /// Flag to write filenames as comments (on by default)
+    #[arg(long, action = ArgAction::SetTrue, default_value_t = true, overrides_with = "no_write_filenames")]
+    pub write_filenames: bool,
+
+    /// Leave out the filename comments
+    #[arg(long, overrides_with = "write_filenames")]
+    pub no_write_filenames: bool,
+
+    /// Flag to write directory tree at the top of the output file (on by default)
+    #[arg(long, action = ArgAction::SetTrue, default_value_t = true, overrides_with = "no_write_tree")]
+    pub write_tree: bool,
+
+    /// Leave out the directory tree
+    #[arg(long, overrides_with = "write_tree")]
+    pub no_write_tree: bool,
+
+    /// Only show files that are included, and the directories containing them, in the tree
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub filtered_tree: bool,
+
+    /// Write just the tree of the matched files, without reading their contents
+    #[arg(long, action = ArgAction::SetTrue, conflicts_with = "no_write_tree")]
+    pub tree_only: bool,
+
+    /// Write the tree and each file's filename comment, but not the files' contents
+    #[arg(long, action = ArgAction::SetTrue, conflicts_with = "tree_only")]
+    pub no_content: bool,
+
+    /// Write filenames relative to the input directory they were found in
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub relative_paths: bool,
+
+    /// Write filenames and tree roots relative to BASE, e.g. the repository root; files outside it are shown absolute
+    #[arg(long, value_name = "BASE", conflicts_with = "relative_paths")]
+    pub relative_to: Option<PathBuf>,
+
+    /// Add a short SHA-256 of each file's contents to its filename comment
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub checksums: bool,
+
+    /// Write a heading such as `// === src/components/ ===` before the first file of each directory
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub group_by_dir: bool,
+
+    /// Collapse directories deeper than N levels in the tree (independent of --max-depth)
+    #[arg(long, value_name = "N")]
+    pub tree_depth: Option<usize>,
+
+    /// Order of entries within each directory of the tree
+    #[arg(long, value_enum, default_value_t = TreeSort::DirsFirst)]
+    pub tree_sort: TreeSort,
+
+    /// Show each file's size in the tree
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub tree_sizes: bool,
+
+    /// Draw the tree with ASCII connectors (|-- and `--) instead of Unicode box-drawing characters
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub tree_ascii: bool,
+
+    /// Comment style to use for filenames (default: //)
+    #[arg(long, default_value = "//")]
+    pub comment_style: String,
+
+    /// Pick the filename comment style from each file's extension, falling back to --comment-style
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub auto_comment: bool,
+
+    /// Include hidden files and directories (names starting with .)
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub hidden: bool,
+
+    /// Skip files and directories ignored by .gitignore files
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub respect_gitignore: bool,
+
+    /// Skip files that look like binary content
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub skip_binary: bool,
+
+    /// Embed files that look like binary content as base64, after a comment giving their size, instead of writing raw bytes
+    #[arg(long, action = ArgAction::SetTrue, conflicts_with = "skip_binary")]
+    pub binary_as_base64: bool,
+
+    /// How to handle file contents that aren't valid UTF-8
+    #[arg(long, value_enum, default_value_t = Encoding::Raw)]
+    pub encoding: Encoding,
+
+    /// Line endings to rewrite file contents to
+    #[arg(long, value_enum, default_value_t = LineEndings::Preserve)]
+    pub line_endings: LineEndings,
+
+    /// Remove comments from files in recognized languages
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub strip_comments: bool,
+
+    /// Collapse runs of blank lines in file contents into a single blank line
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub squeeze_blank: bool,
+
+    /// Remove trailing spaces and tabs from every line, except in whitespace-sensitive files such as Python or Markdown
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub trim_trailing: bool,
+
+    /// Expand tabs in indentation to stops every N columns, except in whitespace-sensitive files
+    #[arg(
+        long,
+        value_name = "N",
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..),
+        conflicts_with = "spaces_to_tabs"
+    )]
+    pub tabs_to_spaces: Option<usize>,
+
+    /// Indent with a tab for every N columns of indentation, except in whitespace-sensitive files
+    #[arg(long, value_name = "N", value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    pub spaces_to_tabs: Option<usize>,
+
+    /// End each file with exactly one newline, instead of always adding one after its contents
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub ensure_trailing_newline: bool,
+
+    /// Indent each file's contents according to how deeply it is nested in the input directory
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub indent_by_depth: bool,
+
+    /// Spaces per level of nesting for --indent-by-depth
+    #[arg(long, value_name = "N", default_value_t = 2)]
+    pub indent_width: usize,
+
+    /// Prefix each line of file contents with its line number
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub line_numbers: bool,
+
+    /// Syntax-highlight source files in plain and Markdown output (the binary only does so on a terminal)
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub highlight: bool,
+
+    /// Number of threads used to read files (defaults to the number of logical CPUs)
+    #[arg(short, long, value_name = "N")]
+    pub jobs: Option<usize>,
+
+    /// Include symlinked files and descend into symlinked directories, which are skipped otherwise (cycles are detected and skipped)
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub follow_symlinks: bool,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t = OutputFormat::Plain)]
+    pub format: OutputFormat,
+
+    /// With --format markdown, split files longer than N lines across several code blocks
+    #[arg(long, value_name = "N")]
+    pub chunk_lines: Option<usize>,
+
+    /// Text written at the very top of the output; {date}, {dir} and {file_count} are substituted (supports \n and \t escapes)
+    #[arg(long, value_name = "STRING", value_parser = parse_escapes, conflicts_with = "header_file")]
+    pub header: Option<String>,
+
+    /// Like --header, but reads the template from FILE
+    #[arg(long, value_name = "FILE")]
+    pub header_file: Option<PathBuf>,
+
+    /// Write the contents of FILE verbatim at the very start of the output
+    #[arg(long, value_name = "FILE")]
+    pub prepend_file: Option<PathBuf>,
+
+    /// Write the contents of FILE verbatim at the very end of the output
+    #[arg(long, value_name = "FILE")]
+    pub append_file: Option<PathBuf>,
+
+    /// Write a numbered table of contents, with the line each file starts on, before the file contents (ignored for JSON)
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub toc: bool,
+
+    /// Line written between files, e.g. ---- (supports \n and \t escapes)
+    #[arg(long, value_name = "STRING", value_parser = parse_escapes)]
+    pub separator: Option<String>,
+
+    /// Write each file as TEMPLATE in the plain format instead of a filename comment and its contents, e.g. '=== {relpath} ({size}) ===\n{content}'; also takes {path}, {ext}, {lang} and {index}
+    #[arg(long, value_name = "TEMPLATE", value_parser = parse_template)]
+    pub template: Option<Template>,
+
+    /// Number of extra blank lines written between files
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pub blank_lines: usize,
+
+    /// Skip empty files, such as __init__.py or .gitkeep placeholders
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub exclude_empty: bool,
+
+    /// Skip lockfiles and generated files such as Cargo.lock, package-lock.json, *.min.js and *.map
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub skip_lockfiles: bool,
+
+    /// Leave out files smaller than this size, e.g. 100 or 2k
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub min_file_size: Option<u64>,
+
+    /// Skip files larger than this size, e.g. 10k or 2M, noting them in the output
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub max_file_size: Option<u64>,
+
+    /// Only include files modified within this long ago, e.g. 30m, 2h, 3d or 1w
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub modified_within: Option<Duration>,
+
+    /// Only include files last modified more than this long ago, e.g. 1w
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub modified_before: Option<Duration>,
+
+    /// Rewrite file contents with a regex substitution, e.g. 'token=\w+=>token=REDACTED' (repeatable, applied in order)
+    #[arg(long, value_name = "PATTERN=>REPLACEMENT", value_parser = parse_replacement)]
+    pub replace: Vec<Replacement>,
+
+    /// Keep only the first this many bytes of larger files, noting how much was cut
+    #[arg(long, value_name = "BYTES")]
+    pub truncate_file: Option<usize>,
+
+    /// Keep only the first N lines of each file, noting how many were left out
+    #[arg(long, value_name = "N")]
+    pub head: Option<usize>,
+
+    /// Keep only the last N lines of each file; with --head, the lines in between are left out
+    #[arg(long, value_name = "N")]
+    pub tail: Option<usize>,
+
+    /// Allow writing the output file inside an input directory, which is refused otherwise; it is still left out of the selection
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub allow_output_in_tree: bool,
+
+    /// Stop at the first file that can't be read instead of noting it and carrying on
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub fail_fast: bool,
+
+    /// Log each file or directory that is left out, and why, to stderr
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub explain_exclusions: bool,
+
+    /// Don't print warnings or status messages on stderr, only errors
+    #[arg(short, long, action = ArgAction::SetTrue, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Print each file as it is written and why others are left out; repeat to also list directories as they are walked
+    #[arg(short, long, action = ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Show a live count of files and bytes read on stderr
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub progress: bool,
+
+    /// Write a summary of file and byte counts at the end of the output and to stderr
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub summary: bool,
+
+    /// Print an estimate of the output's token count to stderr
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub count_tokens: bool,
+
+    /// Stop including files once the estimated token count would exceed N
+    #[arg(long, value_name = "N")]
+    pub max_tokens: Option<u64>,
+
+    /// Stop including files once the output would grow past this size, e.g. 10M, noting how many were left out.
+    /// Notes about skipped and omitted files don't count towards it
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub max_total_size: Option<u64>,
+
+    /// Order in which entries of each directory are emitted
+    #[arg(long, value_enum, default_value_t = SortMode::Path)]
+    pub sort: SortMode,
+
+    /// Whether subdirectories are walked depth-first or breadth-first
+    #[arg(long, value_enum, default_value_t = Traversal::Dfs)]
+    pub traversal: Traversal,
+}
+
+/// Include and `!`-prefixed exclude glob patterns that paths are matched against.
+pub struct FileFilter {
+    include: GlobSet,
+    /// The include patterns, in the same order as the globs in `include`.
+    include_patterns: Vec<String>,
+    exclude: GlobSet,
+    /// The exclude patterns without their `!`, in the same order as the globs in `exclude`.
+    exclude_patterns: Vec<String>,
+    include_all: bool,
+    /// Input directories, as given and canonicalized, that paths are matched relative to.
+    roots: Vec<PathBuf>,
+}
+
+impl FileFilter {
+    /// Builds a filter from `patterns`; with no include patterns every path is included.
+    pub fn new(patterns: &[String], case_insensitive: bool) -> Result<Self, globset::Error> {
+        let glob = |pattern: &str| {
+            GlobBuilder::new(pattern)
+                .case_insensitive(case_insensitive)
+                .build()
+        };
+        let mut include_builder = GlobSetBuilder::new();
+        let mut exclude_builder = GlobSetBuilder::new();
+        let mut include_patterns = Vec::new();
+        let mut exclude_patterns = Vec::new();
+        let mut include_all = true;
+
+        for pattern in patterns {
+            if let Some(pattern) = pattern.strip_prefix('!') {
+                exclude_builder.add(glob(pattern)?);
+                exclude_patterns.push(pattern.to_owned());
+            } else {
+                include_builder.add(glob(pattern)?);
+                include_patterns.push(pattern.clone());
+                include_all = false;
+            }
+        }
+
+        if include_all {
+            include_builder.add(Glob::new("**/*")?);
+        }
+
+        Ok(FileFilter {
+            include: include_builder.build()?,
+            include_patterns,
+            exclude: exclude_builder.build()?,
+            exclude_patterns,
+            include_all,
+            roots: Vec::new(),
+        })
+    }
+
+    /// Anchors the patterns to `directories`, so that `src/*.ts` matches
+    /// `src/main.ts` inside any of them. Paths outside them are matched whole.
+    pub fn with_roots(mut self, directories: &[PathBuf]) -> Self {
+        for directory in directories {
+            self.roots.push(directory.clone());
+            if let Ok(canonical) = fs::canonicalize(directory) {
+                self.roots.push(canonical);
+            }
+        }
+        self
+    }
+
+    /// Whether `path` matches an include pattern and no exclude pattern.
+    pub fn should_process(&self, path: &Path) -> bool {
+        self.exclusion(path).is_none()
+    }
+
+    /// The position, among the include patterns, of the first one matching `path`.
+    pub fn first_include_match(&self, path: &Path) -> Option<usize> {
+        if self.include_all {
+            return None;
+        }
+        self.include.matches(self.relative(path)).into_iter().min()
+    }
+
+    /// `path` relative to the first root it is under, or `path` itself.
+    fn relative<'p>(&self, path: &'p Path) -> &'p Path {
+        self.roots
+            .iter()
+            .find_map(|root| path.strip_prefix(root).ok())
+            .unwrap_or(path)
+    }
+
+    /// Explains why `path` doesn't pass the filter, or returns `None` if it does.
+    fn exclusion(&self, path: &Path) -> Option<Exclusion<'_>> {
+        let path = self.relative(path);
+        if !self.include_all && !self.include.is_match(path) {
+            return Some(Exclusion::NoIncludeMatch);
+        }
+        if !self.exclude.is_match(path) {
+            return None;
+        }
+        let index = self.exclude.matches(path)[0];
+        Some(Exclusion::ExcludePattern(&self.exclude_patterns[index]))
+    }
+
+    /// Returns the include patterns that match none of `paths`.
+    fn unmatched_patterns<'a>(&self, paths: impl Iterator<Item = &'a Path>) -> Vec<&str> {
+        let mut match_counts = vec![0usize; self.include_patterns.len()];
+        for path in paths {
+            for index in self.include.matches(self.relative(path)) {
+                // Skips the implicit `**/*` glob added when no include patterns are given.
+                if let Some(count) = match_counts.get_mut(index) {
+                    *count += 1;
+                }
+            }
+        }
+
+        self.include_patterns
+            .iter()
+            .zip(match_counts)
+            .filter(|(_, count)| *count == 0)
+            .map(|(pattern, _)| pattern.as_str())
+            .collect()
+    }
+}
+
+/// Why a file or directory was left out, as reported by `--explain-exclusions`.
+enum Exclusion<'a> {
+    Hidden,
+    Gitignored(&'a ignore::gitignore::Glob),
+    NotRecursive,
+    ExcludedDir,
+    MaxDepth,
+    MinDepth,
+    Symlink,
+    Output,
+    Empty,
+    TooSmall,
+    TooOld,
+    TooNew,
+    Unchanged,
+    DirLimit,
+    GrepMatch,
+    NoGrepMatch,
+    NoIncludeMatch,
+    ExcludePattern(&'a str),
+}
+
+impl fmt::Display for Exclusion<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Exclusion::Hidden => write!(f, "hidden (use --hidden)"),
+            Exclusion::Gitignored(rule) => match rule.from() {
+                Some(from) => write!(
+                    f,
+                    "gitignore rule {} in {}",
+                    rule.original(),
+                    from.display()
+                ),
+                None => write!(f, "gitignore rule {}", rule.original()),
+            },
+            Exclusion::NotRecursive => write!(f, "subdirectory skipped by --no-recursive"),
+            Exclusion::ExcludedDir => write!(f, "directory pruned by --exclude-dir"),
+            Exclusion::MaxDepth => write!(f, "deeper than --max-depth"),
+            Exclusion::MinDepth => write!(f, "shallower than --min-depth"),
+            Exclusion::Symlink => write!(f, "symlink (use --follow-symlinks)"),
+            Exclusion::Output => write!(f, "the output file itself"),
+            Exclusion::Empty => write!(f, "empty file (--exclude-empty)"),
+            Exclusion::TooSmall => write!(f, "smaller than --min-file-size"),
+            Exclusion::TooOld => write!(f, "not modified within --modified-within"),
+            Exclusion::TooNew => write!(f, "modified more recently than --modified-before"),
+            Exclusion::Unchanged => write!(f, "unchanged since --since reference"),
+            Exclusion::DirLimit => write!(f, "its directory reached --max-files-per-dir"),
+            Exclusion::GrepMatch => write!(f, "contents match --grep (--grep-invert)"),
+            Exclusion::NoGrepMatch => write!(f, "contents don't match --grep"),
+            Exclusion::NoIncludeMatch => write!(f, "matched no include pattern"),
+            Exclusion::ExcludePattern(pattern) => write!(f, "exclude pattern !{}", pattern),
+        }
+    }
+}
+
+/// Logs why `path` was left out, if `--explain-exclusions` or `--verbose` is set.
+fn explain_exclusion(options: &ConcatOptions, path: &Path, reason: impl fmt::Display) {
+    if options.explain_exclusions || options.verbose > 0 {
+        eprintln!("excluded {}: {}", path.display(), reason);
+    }
+}
+
+/// Prints a warning on stderr unless `--quiet` is set.
+fn warn(options: &ConcatOptions, message: impl fmt::Display) {
+    if !options.quiet {
+        eprintln!("warning: {}", message);
+    }
+}
+
+/// Parses a size in bytes with an optional binary `k`, `M` or `G` suffix,
+/// like `512`, `10k` or `2M`.
+fn parse_size(value: &str) -> Result<u64, String> {
+    let lower = value.trim().to_ascii_lowercase();
+    let digits = lower.strip_suffix('b').unwrap_or(&lower);
+    let (digits, multiplier) = match digits.char_indices().last() {
+        Some((i, 'k')) => (&digits[..i], 1 << 10),
+        Some((i, 'm')) => (&digits[..i], 1 << 20),
+        Some((i, 'g')) => (&digits[..i], 1 << 30),
+        _ => (digits, 1),
+    };
+    digits
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| format!("invalid size '{}', expected e.g. 512, 10k or 2M", value))
+}
+
+/// Parses a duration with an `s`, `m`, `h`, `d` or `w` suffix, like `30m`,
+/// `2h` or `3d`.
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let seconds = match value.char_indices().last() {
+        Some((i, unit)) => {
+            let multiplier = match unit {
+                's' => 1,
+                'm' => 60,
+                'h' => 60 * 60,
+                'd' => 24 * 60 * 60,
+                'w' => 7 * 24 * 60 * 60,
+                _ => 0,
+            };
+            value[..i]
+                .trim()
+                .parse::<u64>()
+                .ok()
+                .filter(|_| multiplier > 0)
+                .and_then(|n| n.checked_mul(multiplier))
+        }
+        None => None,
+    };
+    seconds
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("invalid duration '{}', expected e.g. 30m, 2h or 3d", value))
+}
+
+/// Parses a `PATTERN=>REPLACEMENT` substitution, splitting at the first `=>`.
+fn parse_replacement(value: &str) -> Result<Replacement, String> {
+    let (pattern, replacement) = value.split_once("=>").ok_or_else(|| {
+        format!(
+            "invalid replacement '{}', expected PATTERN=>REPLACEMENT",
+            value
+        )
+    })?;
+    let pattern = regex::bytes::Regex::new(pattern).map_err(|e| e.to_string())?;
+    Ok(Replacement {
+        pattern,
+        replacement: replacement.to_string(),
+    })
+}
+
+/// Parses a per-file template, expanding escapes like `--separator` does.
+fn parse_template(value: &str) -> Result<Template, String> {
+    parse_escapes(value)?.parse()
+}
+
+/// Expands `\n`, `\t` and `\\` escapes in a command-line argument.
+fn parse_escapes(value: &str) -> Result<String, String> {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some('\\') => unescaped.push('\\'),
+            Some(other) => return Err(format!("unknown escape sequence \\{}", other)),
+            None => unescaped.push('\\'),
+        }
+    }
+    Ok(unescaped)
+}
+
+/// Name of the file, looked up in each input directory, listing patterns to always exclude.
+const IGNORE_FILE_NAME: &str = ".concactiignore";
+
+/// Directories skipped with `--prune-defaults`.
+const PRUNE_DEFAULTS: &[&str] = &[
+    "node_modules",
+    "target",
+    "dist",
+    "build",
+    "vendor",
+    "__pycache__",
+    ".git",
+    ".venv",
+    "venv",
+];
+
+/// Files excluded with `--skip-lockfiles`.
+const LOCKFILE_PATTERNS: &[&str] = &[
+    "**/package-lock.json",
+    "**/npm-shrinkwrap.json",
+    "**/yarn.lock",
+    "**/pnpm-lock.yaml",
+    "**/bun.lockb",
+    "**/Cargo.lock",
+    "**/Gemfile.lock",
+    "**/poetry.lock",
+    "**/Pipfile.lock",
+    "**/composer.lock",
+    "**/go.sum",
+    "**/*.min.js",
+    "**/*.min.css",
+    "**/*.map",
+];
+
+impl ConcatOptions {
+    /// The filter files must pass, combining `patterns` with those from
+    /// `include_from` files, `ext`/`no_ext`, `skip_lockfiles`, and each
+    /// root's `.concactiignore`.
+    pub fn file_filter(&self) -> io::Result<FileFilter> {
+        build_file_filter(self)
+    }
+}
+
+/// Combines the command-line patterns with those from `--include-from` files,
+/// `--ext`/`--no-ext`, `--skip-lockfiles`, and each root's `.concactiignore`.
+fn build_file_filter(options: &ConcatOptions) -> io::Result<FileFilter> {
+    let mut patterns: Vec<String> = options
+        .patterns
+        .iter()
+        .flat_map(|patterns| split_patterns(patterns))
+        .collect();
+
+    for pattern_file in &options.include_from {
+        patterns.extend(read_pattern_lines(pattern_file)?);
+    }
+
+    patterns.extend(options.ext.iter().map(|ext| extension_glob(ext)));
+    patterns.extend(
+        options
+            .no_ext
+            .iter()
+            .map(|ext| format!("!{}", extension_glob(ext))),
+    );
+    if options.skip_lockfiles {
+        patterns.extend(
+            LOCKFILE_PATTERNS
+                .iter()
+                .map(|pattern| format!("!{}", pattern)),
+        );
+    }
+
+    for directory in &options.directories {
+        let ignore_file = directory.join(IGNORE_FILE_NAME);
+        if ignore_file.is_file() {
+            patterns.extend(
+                read_pattern_lines(&ignore_file)?
+                    .into_iter()
+                    .map(|pattern| format!("!{}", pattern)),
+            );
+        }
+    }
+
+    FileFilter::new(&patterns, options.case_insensitive)
+        .map(|filter| filter.with_roots(&options.directories))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+/// Splits a comma-separated list of patterns, leaving the commas between
+/// `{...}` alternatives, as in `**/*.{ts,tsx}`, inside their pattern.
+fn split_patterns(list: &str) -> Vec<String> {
+    let mut patterns = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0usize;
+    for c in list.chars() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                patterns.push(std::mem::take(&mut current));
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    patterns.push(current);
+    patterns.retain(|pattern| !pattern.is_empty());
+    patterns
+}
+
+/// Turns an extension such as `ts` or `.ts` into the glob `**/*.ts`.
+fn extension_glob(ext: &str) -> String {
+    format!("**/*.{}", ext.trim().trim_start_matches('.'))
+}
+
+/// Reads one pattern per line, skipping blank lines and `#` comments.
+fn read_pattern_lines(path: &Path) -> io::Result<Vec<String>> {
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_owned)
+        .collect())
+}
+
+/// Concatenates the files selected by a set of [`ConcatOptions`].
+#[derive(Clone, Debug)]
+pub struct Concatenator {
+    options: ConcatOptions,
+    /// Canonical path of the output file, which is never included.
+    output_path: Option<PathBuf>,
+}
+
+impl Concatenator {
+    pub fn new(options: ConcatOptions) -> Self {
+        Concatenator {
+            options,
+            output_path: None,
+        }
+    }
+
+    pub fn options(&self) -> &ConcatOptions {
+        &self.options
+    }
+
+    /// Leaves the file at `path` out of the selection, so output written
+    /// inside an input directory doesn't include itself. The file needn't
+    /// exist yet, but its directory must. Selecting then fails if `path` is
+    /// inside an input directory, unless `allow_output_in_tree` is set.
+    pub fn exclude_output(&mut self, path: &Path) -> io::Result<()> {
+        let canonical = match fs::canonicalize(path) {
+            Ok(canonical) => canonical,
+            Err(error) => {
+                let name = path.file_name().ok_or(error)?;
+                let parent = path
+                    .parent()
+                    .filter(|parent| !parent.as_os_str().is_empty());
+                fs::canonicalize(parent.unwrap_or(Path::new(".")))?.join(name)
+            }
+        };
+        self.output_path = Some(canonical);
+        Ok(())
+    }
+
+    /// The files to concatenate, either from `files_from` or by walking the
+    /// input directories, in output order.
+    pub fn select_files(&self) -> io::Result<Vec<CollectedFile>> {
+        select_files(&self.options, self.output_path.as_deref())
+    }
+
+    /// Selects the files and writes them, with the trees of the input directories.
+    /// The elapsed time covers both steps.
+    pub fn concatenate<W: Write>(&self, writer: W) -> io::Result<Stats> {
+        let start = Instant::now();
+        let files = self.select_files()?;
+        let mut stats = self.write_files(writer, &files, &self.options.directories)?;
+        stats.elapsed = start.elapsed();
+        Ok(stats)
+    }
+
+    /// Writes `files`, preceded by the trees of `tree_roots` if enabled.
+    pub fn write_files<W: Write>(
+        &self,
+        writer: W,
+        files: &[CollectedFile],
+        tree_roots: &[PathBuf],
+    ) -> io::Result<Stats> {
+        write_files(&self.options, writer, files, tree_roots)
+    }
+}
+
+/// Writes `files`, preceded by the trees of `tree_roots` if enabled.
+fn write_files<W: Write>(
+    options: &ConcatOptions,
+    writer: W,
+    files: &[CollectedFile],
+    tree_roots: &[PathBuf],
+) -> io::Result<Stats> {
+    let start = Instant::now();
+    if options.highlight {
+        highlight::check_supported()?;
+    }
+    let mut writer = CountingWriter::new(writer);
+
+    let template = match &options.header_file {
+        Some(header_file) => Some(fs::read_to_string(header_file)?),
+        None => options.header.clone(),
+    };
+    let header = template.map(|template| {
+        let values = header::HeaderValues {
+            date: header::today(),
+            directories: &options.directories,
+            file_count: files.len(),
+        };
+        header::render_header(&template, &values)
+    });
+    let prepend = options
+        .prepend_file
+        .as_ref()
+        .map(fs::read_to_string)
+        .transpose()?;
+    let append = options
+        .append_file
+        .as_ref()
+        .map(fs::read_to_string)
+        .transpose()?;
+    if let Some(prepend) = prepend.as_ref().filter(|_| !options.format.is_structured()) {
+        write!(writer, "{}", prepend)?;
+        if !prepend.ends_with('\n') {
+            writeln!(writer)?;
+        }
+    }
+    if let Some(header) = header.as_ref().filter(|_| !options.format.is_structured()) {
+        write!(writer, "{}", header)?;
+        if !header.ends_with('\n') {
+            writeln!(writer)?;
+        }
+    }
+
+    let mut trees = Vec::new();
+    let write_tree = options.write_tree || options.tree_only;
+    if write_tree {
+        let paths: Vec<&Path> = files.iter().map(|file| file.path.as_path()).collect();
+        let tree_options = tree::TreeOptions {
+            sort: options.tree_sort,
+            sizes: options.tree_sizes,
+            max_depth: options.tree_depth,
+        };
+        for directory in tree_roots {
+            // The walk already applied the patterns, depth limits and hidden-file rules.
+            let mut tree = if options.filtered_tree || options.tree_only {
+                tree::filtered_tree(directory, &paths, &tree_options)?
+            } else {
+                // Hidden and pruned entries are left out, just as the walk leaves them out.
+                tree::tree(directory, &tree_options, |entry| {
+                    let name = entry.file_name();
+                    (!options.hidden && name.as_encoded_bytes().starts_with(b"."))
+                        || (entry.path().is_dir() && is_pruned(options, &name))
+                })?
+            };
+            if options.tree_ascii {
+                tree::use_ascii_glyphs(&mut tree);
+            }
+            if let Some(base) = &options.relative_to {
+                tree.root = relative_to(directory, base).display().to_string();
+            }
+            trees.push(tree);
+        }
+    }
+    match options.format {
+        OutputFormat::Plain => {
+            for tree in &trees {
+                writeln!(writer, "{}", tree)?;
+            }
+        }
+        OutputFormat::Markdown => {
+            for tree in &trees {
+                writeln!(writer, "```text\n{}```\n", tree)?;
+            }
+        }
+        OutputFormat::Json => {
+            let trees = write_tree.then(|| trees.into_iter().map(json::JsonTree::from).collect());
+            json::write_start(&mut writer, prepend.as_deref(), header.as_deref(), trees)?;
+        }
+        OutputFormat::Xml => {
+            let trees = write_tree.then_some(trees.as_slice());
+            xml::write_start(&mut writer, prepend.as_deref(), header.as_deref(), trees)?;
+        }
+    }
+
+    let files = if options.tree_only { &[] } else { files };
+
+    // Reading happens on the pool, but results are collected in traversal
+    // order so the output stays deterministic.
+    let pool = thread_pool(options)?;
+    let progress = options
+        .progress
+        .then(|| progress::Progress::new(files.len()));
+    let contents: Vec<io::Result<FileContents>> = pool.install(|| {
+        files
+            .par_iter()
+            .map(|file| {
+                let contents = if options.no_content {
+                    leave_out_contents(options, file)
+                } else {
+                    read_file(options, file)
+                };
+                if let Some(progress) = &progress {
+                    progress.record(file.len);
+                }
+                contents
+            })
+            .collect()
+    });
+    if let Some(progress) = &progress {
+        progress.finish();
+    }
+
+    let mut stats = Stats::default();
+    // Files left out once the token budget or size cap ran out, and which it was.
+    const OVER_TOKENS: &str = "over the token budget";
+    const OVER_SIZE: &str = "over --max-total-size";
+    let mut dropped = Vec::new();
+    let mut drop_reason = "";
+    // Bytes of skip notes so far, which `--max-total-size` doesn't count.
+    let mut notes_len = 0;
+    // With a table of contents, which must come first, the file contents are
+    // held back in `body` until every file's starting line is known.
+    let toc = options.toc && !options.format.is_structured();
+    let mut body = Vec::new();
+    let mut toc_entries = Vec::new();
+    // Directory of the last file written, for `--group-by-dir`.
+    let mut previous_dir = None;
+
+    // The template takes the place of the filename comments and separators.
+    let template = options
+        .template
+        .as_ref()
+        .filter(|_| options.format == OutputFormat::Plain);
+    let display_paths: Vec<Cow<Path>> = files
+        .iter()
+        .map(|file| file.display_path(options))
+        .collect();
+
+    for ((file, contents), path) in files.iter().zip(contents).zip(&display_paths) {
+        let path: &Path = path;
+        if !dropped.is_empty() {
+            dropped.push(path);
+            stats.skip(file, drop_reason);
+            continue;
+        }
+
+        let contents = match contents {
+            Ok(contents) => contents,
+            Err(error) if options.fail_fast => {
+                writer.flush()?;
+                return Err(error);
+            }
+            Err(error) => {
+                eprintln!("error reading {}: {}", file.path.display(), error);
+                FileContents::Skipped(format!("error reading: {}", error))
+            }
+        };
+        let (contents, checksum) = match contents {
+            FileContents::Included(contents, checksum) => (contents, checksum),
+            FileContents::Skipped(note) => {
+                explain_exclusion(options, path, &note);
+                let first = stats.files + stats.skipped.len() == 0;
+                let before = writer.count() + body.len() as u64;
+                if toc {
+                    write_note(&mut body, options, file, &note, first)?;
+                } else {
+                    write_note(&mut writer, options, file, &note, first)?;
+                }
+                notes_len += writer.count() + body.len() as u64 - before;
+                stats.skip(file, note);
+                continue;
+            }
+        };
+
+        // Render the whole section first so it can be measured against the budget.
+        let mut section = Vec::new();
+        if !options.format.is_structured() && template.is_none() && stats.files > 0 {
+            section.resize(options.blank_lines, b'\n');
+            if let Some(separator) = &options.separator {
+                writeln!(section, "{}", separator)?;
+            }
+        }
+        let dir = path.parent().unwrap_or(Path::new(""));
+        if options.group_by_dir && previous_dir != Some(dir) {
+            write_dir_heading(&mut section, options, dir)?;
+        }
+        let start_line = count_lines(&body) + count_lines(&section);
+        let entry_start = section.len();
+        // Counted before highlighting adds escape codes.
+        let len = if options.no_content {
+            file.len as usize
+        } else {
+            contents.len()
+        };
+        let contents = if options.highlight && !options.format.is_structured() {
+            highlight::highlight(path, &contents).unwrap_or(contents)
+        } else {
+            contents
+        };
+        match options.format {
+            OutputFormat::Plain => match template {
+                Some(template) => {
+                    let index = stats.files + 1;
+                    write_templated(
+                        &mut section,
+                        options,
+                        template,
+                        file,
+                        path,
+                        &contents,
+                        index,
+                    )?
+                }
+                None => {
+                    if options.write_filenames {
+                        let name = match &checksum {
+                            Some(checksum) => format!("{} [{}]", path.display(), checksum),
+                            None => path.display().to_string(),
+                        };
+                        write_comment(&mut section, options, path, &name)?;
+                    }
+                    if !options.no_content {
+                        section.extend_from_slice(&contents);
+                        if !(options.ensure_trailing_newline && contents.ends_with(b"\n")) {
+                            writeln!(section)?;
+                        }
+                    }
+                }
+            },
+            OutputFormat::Markdown if options.no_content => {
+                writeln!(section, "### {}\n", path.display())?
+            }
+            OutputFormat::Markdown => {
+                format::write_markdown_file(&mut section, path, &contents, options.chunk_lines)?
+            }
+            OutputFormat::Json => {
+                let first = stats.files + stats.skipped.len() == 0;
+                let entry = json::JsonFile::included(path, file.len, &contents);
+                json::write_file(&mut section, first, &entry)?;
+            }
+            OutputFormat::Xml => xml::write_file(&mut section, path, file.len, &contents)?,
+        }
+
+        let written = writer.count() + body.len() as u64 + section.len() as u64;
+        if options
+            .max_tokens
+            .is_some_and(|max| tokens::estimate_tokens(written) > max)
+        {
+            drop_reason = OVER_TOKENS;
+        } else if options
+            .max_total_size
+            .is_some_and(|max| written - notes_len > max)
+        {
+            drop_reason = OVER_SIZE;
+        }
+        if !drop_reason.is_empty() {
+            dropped.push(path);
+            stats.skip(file, drop_reason);
+            continue;
+        }
+
+        // With a table of contents, offsets are into `body` until it is written.
+        let offset = if toc {
+            body.len() as u64
+        } else {
+            writer.count()
+        };
+        stats.included.push(IncludedFile {
+            path: file.path.clone(),
+            offset: offset + entry_start as u64,
+            length: (section.len() - entry_start) as u64,
+        });
+        if toc {
+            body.extend_from_slice(&section);
+            toc_entries.push((path, start_line));
+        } else {
+            writer.write_all(&section)?;
+        }
+        if options.verbose > 0 {
+            eprintln!("included {} ({} bytes)", path.display(), len);
+        }
+        stats.files += 1;
+        stats.bytes += len as u64;
+        previous_dir = Some(dir);
+    }
+
+    if toc {
+        write_toc(&mut writer, options, &toc_entries)?;
+        for included in &mut stats.included {
+            included.offset += writer.count();
+        }
+        writer.write_all(&body)?;
+    }
+
+    if drop_reason == OVER_SIZE {
+        let note = format!("[{} more files omitted by --max-total-size]", dropped.len());
+        match options.format {
+            OutputFormat::Plain => writeln!(writer, "{} {}", options.comment_style, note)?,
+            OutputFormat::Markdown => writeln!(writer, "_{}_", note)?,
+            // The skipped count in the summary covers them.
+            OutputFormat::Json | OutputFormat::Xml => {}
+        }
+    }
+
+    if !dropped.is_empty() {
+        let limit = if drop_reason == OVER_TOKENS {
+            "token limit"
+        } else {
+            "size limit"
+        };
+        let mut message = format!("{} reached, dropped {} files:", limit, dropped.len());
+        for path in dropped {
+            message.push_str(&format!("\n  {}", path.display()));
+        }
+        warn(options, message);
+    }
+
+    match options.format {
+        OutputFormat::Plain if options.summary => {
+            writeln!(writer, "{} {}", options.comment_style, stats)?
+        }
+        OutputFormat::Markdown if options.summary => writeln!(writer, "_{}_", stats)?,
+        OutputFormat::Json => json::write_end(
+            &mut writer,
+            options.summary.then_some(&stats),
+            append.as_deref(),
+        )?,
+        OutputFormat::Xml => xml::write_end(
+            &mut writer,
+            options.summary.then_some(&stats),
+            append.as_deref(),
+        )?,
+        _ => {}
+    }
+    if let Some(append) = append.filter(|_| !options.format.is_structured()) {
+        write!(writer, "{}", append)?;
+    }
+    if options.count_tokens {
+        eprintln!("~{} tokens", tokens::estimate_tokens(writer.count()));
+    }
+
+    writer.flush()?;
+    stats.elapsed = start.elapsed();
+    Ok(stats)
+}
+
+/// Writes a numbered list of `entries`, each a path and the line it starts on
+/// counted from the end of the table, and updates those lines to account for
+/// everything written before them.
+fn write_toc<W: Write>(
+    writer: &mut CountingWriter<W>,
+    options: &ConcatOptions,
+    entries: &[(&Path, u64)],
+) -> io::Result<()> {
+    // The heading and blank lines around the entries.
+    let extra_lines = match options.format {
+        OutputFormat::Markdown => 3,
+        _ => 2,
+    };
+    let first_body_line = writer.lines() + entries.len() as u64 + extra_lines + 1;
+
+    match options.format {
+        OutputFormat::Markdown => writeln!(writer, "## Contents\n")?,
+        _ => writeln!(writer, "{} Contents", options.comment_style)?,
+    }
+    for (index, (path, start_line)) in entries.iter().enumerate() {
+        let line = first_body_line + start_line;
+        match options.format {
+            OutputFormat::Markdown => writeln!(
+                writer,
+                "{}. `{}` (line {})",
+                index + 1,
+                path.display(),
+                line
+            )?,
+            _ => writeln!(
+                writer,
+                "{} {}. {} (line {})",
+                options.comment_style,
+                index + 1,
+                path.display(),
+                line
+            )?,
+        }
+    }
+    writeln!(writer)
+}
+
+fn count_lines(bytes: &[u8]) -> u64 {
+    bytes.iter().filter(|&&b| b == b'\n').count() as u64
+}
+
+/// What made it into the output, what was left out, and how long it took.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Stats {
+    pub files: usize,
+    /// Selected files that were left out, such as binary or oversized ones.
+    #[serde(serialize_with = "serialize_count")]
+    pub skipped: Vec<SkippedFile>,
+    /// Bytes of file contents written, excluding filename comments and the tree.
+    pub bytes: u64,
+    #[serde(skip)]
+    pub elapsed: Duration,
+    /// Where each included file ended up in the output.
+    #[serde(skip)]
+    pub included: Vec<IncludedFile>,
+}
+
+/// A file written to the output, and the byte range of its entry there,
+/// including its filename comment or heading. Offsets count the bytes as
+/// written, before any compression. They are UTF-8 bytes, so the binary
+/// rejects `--manifest` with any other `--output-encoding`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct IncludedFile {
+    pub path: PathBuf,
+    pub offset: u64,
+    pub length: u64,
+}
+
+/// A selected file that was left out of the output, and why.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SkippedFile {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+/// Serializes just the number of items, which is all the JSON summary shows.
+fn serialize_count<S: serde::Serializer, T>(items: &[T], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(items.len() as u64)
+}
+
+impl Stats {
+    fn skip(&mut self, file: &CollectedFile, reason: impl Into<String>) {
+        self.skipped.push(SkippedFile {
+            path: file.path.clone(),
+            reason: reason.into(),
+        });
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} files, {} bytes", self.files, self.bytes)?;
+        if !self.skipped.is_empty() {
+            write!(f, ", {} skipped", self.skipped.len())?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes `text` as a single-line comment in the style chosen for `path`.
+fn write_comment<W: Write>(
+    writer: &mut W,
+    options: &ConcatOptions,
+    path: &Path,
+    text: &str,
+) -> io::Result<()> {
+    let (open, close) = options
+        .auto_comment
+        .then(|| comments::delimiters_for(path))
+        .flatten()
+        .unwrap_or((&options.comment_style, ""));
+
+    if close.is_empty() {
+        writeln!(writer, "{} {}", open, text)
+    } else {
+        writeln!(writer, "{} {} {}", open, text, close)
+    }
+}
+
+/// Marks the start of the files in `dir`, in the current format.
+fn write_dir_heading<W: Write>(
+    writer: &mut W,
+    options: &ConcatOptions,
+    dir: &Path,
+) -> io::Result<()> {
+    let dir = match dir.to_string_lossy() {
+        name if name.is_empty() => "./".to_string(),
+        name => format!("{}/", name.trim_end_matches('/')),
+    };
+    match options.format {
+        OutputFormat::Plain => writeln!(writer, "{} === {} ===", options.comment_style, dir),
+        OutputFormat::Markdown => writeln!(writer, "## {}\n", dir),
+        OutputFormat::Json | OutputFormat::Xml => Ok(()),
+    }
+}
+
+/// Writes `file` as `template`, ending it with a newline if the template doesn't.
+fn write_templated(
+    section: &mut Vec<u8>,
+    options: &ConcatOptions,
+    template: &Template,
+    file: &CollectedFile,
+    path: &Path,
+    contents: &[u8],
+    index: usize,
+) -> io::Result<()> {
+    let relpath = file
+        .root
+        .and_then(|root| file.path.strip_prefix(&options.directories[root]).ok())
+        .unwrap_or(path);
+    let values = template::TemplateValues {
+        path: &path.to_string_lossy(),
+        relpath: &relpath.to_string_lossy(),
+        ext: &path.extension().unwrap_or_default().to_string_lossy(),
+        size: file.len,
+        content: contents,
+        lang: format::language_for(path),
+        index,
+    };
+    section.extend_from_slice(&template.render(&values));
+    if !section.ends_with(b"\n") {
+        writeln!(section)?;
+    }
+    Ok(())
+}
+
+/// Records that `file` was left out of the output, in the current format.
+/// `first` says whether this is the first entry written, which JSON needs to
+/// place commas.
+fn write_note<W: Write>(
+    writer: &mut W,
+    options: &ConcatOptions,
+    file: &CollectedFile,
+    note: &str,
+    first: bool,
+) -> io::Result<()> {
+    let path = &file.display_path(options);
+    match options.format {
+        OutputFormat::Plain => {
+            if options.write_filenames {
+                write_comment(
+                    writer,
+                    options,
+                    path,
+                    &format!("[{}] {}", note, path.display()),
+                )?;
+            }
+            Ok(())
+        }
+        OutputFormat::Markdown => writeln!(writer, "### {}\n\n_[{}]_\n", path.display(), note),
+        OutputFormat::Json => json::write_file(
+            writer,
+            first,
+            &json::JsonFile::skipped(path, file.len, note),
+        ),
+        OutputFormat::Xml => xml::write_skipped_file(writer, path, file.len, note),
+    }
+}
+
+/// A file selected during traversal, along with its size from metadata.
+#[derive(Clone, Debug)]
+pub struct CollectedFile {
+    pub path: PathBuf,
+    /// Index into `ConcatOptions::directories` of the root the file was found under,
+    /// or `None` if it came from `--files-from`.
+    pub root: Option<usize>,
+    /// Number of directories between the root and the file; 0 for `--files-from`.
+    pub depth: usize,
+    pub len: u64,
+}
+
+impl CollectedFile {
+    /// The path to show in the output, honoring `--relative-paths` and `--relative-to`.
+    pub fn display_path<'a>(&'a self, options: &ConcatOptions) -> Cow<'a, Path> {
+        if let Some(base) = &options.relative_to {
+            return Cow::Owned(relative_to(&self.path, base));
+        }
+        match self.root {
+            Some(root) if options.relative_paths => Cow::Borrowed(
+                self.path
+                    .strip_prefix(&options.directories[root])
+                    .unwrap_or(&self.path),
+            ),
+            _ => Cow::Borrowed(&self.path),
+        }
+    }
+}
+
+/// `path` relative to `base`, or made absolute if it isn't under `base`.
+/// Neither is required to exist, and symlinks aren't resolved.
+fn relative_to(path: &Path, base: &Path) -> PathBuf {
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let base = std::path::absolute(base).unwrap_or_else(|_| base.to_path_buf());
+    match absolute.strip_prefix(&base) {
+        Ok(relative) if relative.as_os_str().is_empty() => PathBuf::from("."),
+        Ok(relative) => relative.to_path_buf(),
+        Err(_) => absolute,
+    }
+}
+
+/// The outcome of reading a collected file.
+enum FileContents {
+    /// The transformed contents, and the checksum of the file as read if `--checksums` is set.
+    Included(Vec<u8>, Option<String>),
+    /// The file was left out; the note explains why.
+    Skipped(String),
+}
+
+/// Returns the files to concatenate, either from `--files-from` or by walking
+/// the input directories.
+fn select_files(
+    options: &ConcatOptions,
+    output_path: Option<&Path>,
+) -> io::Result<Vec<CollectedFile>> {
+    let mut files = match &options.files_from {
+        Some(list) => {
+            let mut files = if list == Path::new("-") {
+                read_file_list(io::stdin().lock(), options, output_path)?
+            } else {
+                read_file_list(BufReader::new(File::open(list)?), options, output_path)?
+            };
+            files = skip_repeated_files(options, files);
+            files.retain(|file| match grep_exclusion(options, &file.path, file.len) {
+                Some(exclusion) => {
+                    explain_exclusion(options, &file.path, exclusion);
+                    false
+                }
+                None => true,
+            });
+            files
+        }
+        None => {
+            let file_filter = build_file_filter(options)?;
+            let mut matched = Vec::new();
+            let mut files = collect_files(options, &file_filter, output_path, &mut matched)?;
+            // A walk cut short by `--limit`, or narrowed down by `--since`, says
+            // nothing about what the patterns would match.
+            let cut_short =
+                !reorders_files(options) && options.limit.is_some_and(|limit| files.len() >= limit);
+            if options.since.is_none() && !cut_short {
+                check_unmatched_patterns(options, &file_filter, &matched)?;
+            }
+            if options.order_by_pattern {
+                // Stable, so each bucket keeps the traversal order.
+                files.sort_by_cached_key(|file| file_filter.first_include_match(&file.path));
+            }
+            files
+        }
+    };
+    match options.sort_by_size {
+        // Stable, so files of the same size keep the traversal order.
+        Some(SizeOrder::Smallest) => files.sort_by_key(|file| file.len),
+        Some(SizeOrder::Largest) => files.sort_by_key(|file| std::cmp::Reverse(file.len)),
+        None => {}
+    }
+    if !options.order_file.is_empty() {
+        pin_files(options, &mut files);
+    }
+    files.truncate(options.limit.unwrap_or(usize::MAX));
+    Ok(files)
+}
+
+/// Whether the selected files are reordered after the walk, so that `--limit`
+/// can only be applied once every file is known.
+fn reorders_files(options: &ConcatOptions) -> bool {
+    options.order_by_pattern || options.sort_by_size.is_some() || !options.order_file.is_empty()
+}
+
+/// Moves the files named by `--order-file` to the front, in the order they
+/// were given, leaving the rest in their order. A name matches a file's path
+/// relative to its input directory, or the path itself.
+fn pin_files(options: &ConcatOptions, files: &mut [CollectedFile]) {
+    let pinned: Vec<&Path> = options
+        .order_file
+        .iter()
+        .map(|pinned| pinned.strip_prefix(".").unwrap_or(pinned))
+        .collect();
+    let position = |file: &CollectedFile| {
+        let relative = file
+            .root
+            .and_then(|root| file.path.strip_prefix(&options.directories[root]).ok());
+        pinned
+            .iter()
+            .position(|&pinned| pinned == file.path || Some(pinned) == relative)
+    };
+    // Stable, so the unpinned files keep their order.
+    files.sort_by_cached_key(|file| position(file).unwrap_or(usize::MAX));
+
+    for (index, name) in options.order_file.iter().enumerate() {
+        if !files.iter().any(|file| position(file) == Some(index)) {
+            warn(
+                options,
+                format_args!("--order-file {} matched no file", name.display()),
+            );
+        }
+    }
+}
+
+/// Why `--grep` leaves out the file at `path`, of `len` bytes, if it does.
+/// Files that can't be read, or that `--max-file-size` will skip unread, are
+/// kept, so they are noted when the output is written. The bytes aren't kept,
+/// so files that pass are read again by `read_file`; holding every candidate
+/// in memory until the output is written would cost more than the second read.
+fn grep_exclusion(options: &ConcatOptions, path: &Path, len: u64) -> Option<Exclusion<'static>> {
+    let grep = options.grep.as_ref()?;
+    if options.max_file_size.is_some_and(|max| len > max) {
+        return None;
+    }
+    let matched = grep.is_match(&fs::read(path).ok()?);
+    match (matched, options.grep_invert) {
+        (true, true) => Some(Exclusion::GrepMatch),
+        (false, false) => Some(Exclusion::NoGrepMatch),
+        _ => None,
+    }
+}
+
+/// A pool of `--jobs` threads for reading files.
+fn thread_pool(options: &ConcatOptions) -> io::Result<rayon::ThreadPool> {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(options.jobs.unwrap_or(0))
+        .build()
+        .map_err(io::Error::other)
+}
+
+/// Warns about include patterns that selected nothing, likely typos.
+/// With `--strict-globs` this is an error instead.
+fn check_unmatched_patterns(
+    options: &ConcatOptions,
+    file_filter: &FileFilter,
+    matched: &[PathBuf],
+) -> io::Result<()> {
+    let unmatched = file_filter.unmatched_patterns(matched.iter().map(PathBuf::as_path));
+    if unmatched.is_empty() {
+        return Ok(());
+    }
+
+    if options.strict_globs {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("patterns matched no files: {}", unmatched.join(", ")),
+        ));
+    }
+    for pattern in unmatched {
+        warn(
+            options,
+            format_args!("pattern '{}' matched no files", pattern),
+        );
+    }
+    Ok(())
+}
+
+/// How the paths in a `--files-from` list are separated.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ListFormat {
+    /// One path per line
+    #[default]
+    Lines,
+    /// A JSON array of path strings
+    Json,
+    /// NUL-separated paths, as printed by `find -print0`
+    Null,
+}
+
+/// Reads a list of paths, bypassing traversal and pattern filtering.
+/// Blank entries and the output file itself are skipped, and so are missing
+/// files, with a warning, unless `--fail-fast` is set.
+fn read_file_list<R: Read>(
+    mut reader: R,
+    options: &ConcatOptions,
+    output_path: Option<&Path>,
+) -> io::Result<Vec<CollectedFile>> {
+    let mut input = Vec::new();
+    reader.read_to_end(&mut input)?;
+    let paths: Vec<String> = match options.input_list_format {
+        ListFormat::Lines => String::from_utf8_lossy(&input)
+            .lines()
+            .map(str::to_string)
+            .collect(),
+        ListFormat::Json => serde_json::from_slice(&input)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+        ListFormat::Null => input
+            .split(|&b| b == 0)
+            .map(|path| String::from_utf8_lossy(path).into_owned())
+            .collect(),
+    };
+    let mut files = Vec::new();
+
+    for line in paths {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let path = PathBuf::from(line);
+        let metadata = match fs::metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(error) if options.fail_fast => {
+                return Err(io::Error::new(
+                    error.kind(),
+                    format!("{}: {}", path.display(), error),
+                ));
+            }
+            Err(error) => {
+                warn(
+                    options,
+                    format_args!("skipping {}: {}", path.display(), error),
+                );
+                continue;
+            }
+        };
+        if let Some(output_path) = output_path {
+            if fs::canonicalize(&path)? == output_path {
+                continue;
+            }
+        }
+
+        files.push(CollectedFile {
+            path,
+            root: None,
+            depth: 0,
+            len: metadata.len(),
+        });
+    }
+
+    Ok(files)
+}
+
+/// Drops files listed more than once, under the same or another path to
+/// them, keeping the first, so they aren't written twice.
+fn skip_repeated_files(options: &ConcatOptions, files: Vec<CollectedFile>) -> Vec<CollectedFile> {
+    let mut seen = HashSet::new();
+    let mut unique = Vec::with_capacity(files.len());
+    for file in files {
+        let canonical = fs::canonicalize(&file.path).unwrap_or_else(|_| file.path.clone());
+        if seen.insert(canonical) {
+            unique.push(file);
+        } else {
+            warn(
+                options,
+                format_args!(
+                    "skipping {}, which is listed more than once",
+                    file.path.display()
+                ),
+            );
+        }
+    }
+    unique
+}
+
+/// Walks every input directory and returns each file that passes the filter,
+/// in traversal order, leaving out the output file itself. Directories shared
+/// between overlapping roots are only walked once. Files that pass the
+/// patterns are added to `matched` before the size, age and content filters
+/// run, so those don't make a pattern look unmatched.
+fn collect_files(
+    options: &ConcatOptions,
+    file_filter: &FileFilter,
+    output_path: Option<&Path>,
+    matched: &mut Vec<PathBuf>,
+) -> io::Result<Vec<CollectedFile>> {
+    let mut files = Vec::new();
+    let mut state = VisitState::default();
+    if let Some(output_path) = output_path.filter(|_| !options.allow_output_in_tree) {
+        check_output_outside_roots(options, output_path)?;
+    }
+    let output_guard = match output_path {
+        Some(output_path) => OutputGuard::new(options, output_path)?,
+        None => None,
+    };
+
+    let limit = match options.limit {
+        Some(limit) if !reorders_files(options) => limit,
+        _ => usize::MAX,
+    };
+    if limit == 0 {
+        return Ok(files);
+    }
+    let now = SystemTime::now();
+    // Files with no modification time, or one in the future, count as just modified.
+    let age = |metadata: &fs::Metadata| {
+        metadata
+            .modified()
+            .ok()
+            .and_then(|modified| now.duration_since(modified).ok())
+            .unwrap_or_default()
+    };
+    // Files taken from each directory so far, for `--max-files-per-dir`.
+    let mut per_dir: HashMap<PathBuf, usize> = HashMap::new();
+
+    for (root, directory) in options.directories.iter().enumerate() {
+        let changed = match &options.since {
+            Some(reference) => Some(git::changed_files(directory, reference)?),
+            None => None,
+        };
+        let flow = visit_dirs(
+            directory,
+            options,
+            &mut |entry, depth| {
+                let path = entry.path();
+                // Follows symlinks, like `is_file`, so linked files report their target's size.
+                let Ok(metadata) = fs::metadata(&path) else {
+                    return Ok(ControlFlow::Continue(()));
+                };
+                if !metadata.is_file() {
+                    return Ok(ControlFlow::Continue(()));
+                }
+                if let Some(guard) = &output_guard {
+                    if guard.is_output(&path)? {
+                        explain_exclusion(options, &path, Exclusion::Output);
+                        return Ok(ControlFlow::Continue(()));
+                    }
+                }
+
+                if let Some(exclusion) = file_filter.exclusion(&path) {
+                    explain_exclusion(options, &path, exclusion);
+                    return Ok(ControlFlow::Continue(()));
+                }
+                matched.push(path.clone());
+
+                if options.exclude_empty && metadata.len() == 0 {
+                    explain_exclusion(options, &path, Exclusion::Empty);
+                } else if options
+                    .min_file_size
+                    .is_some_and(|min| metadata.len() < min)
+                {
+                    explain_exclusion(options, &path, Exclusion::TooSmall);
+                } else if options
+                    .modified_within
+                    .is_some_and(|within| age(&metadata) > within)
+                {
+                    explain_exclusion(options, &path, Exclusion::TooOld);
+                } else if options
+                    .modified_before
+                    .is_some_and(|before| age(&metadata) <= before)
+                {
+                    explain_exclusion(options, &path, Exclusion::TooNew);
+                } else if changed
+                    .as_ref()
+                    .is_some_and(|changed| !changed.contains(&path))
+                {
+                    explain_exclusion(options, &path, Exclusion::Unchanged);
+                } else if let Some(exclusion) = grep_exclusion(options, &path, metadata.len()) {
+                    // Checked last, since it reads the file, but before the limits count it.
+                    explain_exclusion(options, &path, exclusion);
+                } else if options.max_files_per_dir.is_some_and(|max| {
+                    let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+                    let taken = per_dir.entry(dir).or_default();
+                    *taken += 1;
+                    *taken > max
+                }) {
+                    explain_exclusion(options, &path, Exclusion::DirLimit);
+                } else {
+                    files.push(CollectedFile {
+                        path,
+                        root: Some(root),
+                        depth,
+                        len: metadata.len(),
+                    });
+                    if files.len() >= limit {
+                        return Ok(ControlFlow::Break(()));
+                    }
+                }
+                Ok(ControlFlow::Continue(()))
+            },
+            0,
+            &mut state,
+        )?;
+        if flow.is_break() {
+            break;
+        }
+    }
+
+    Ok(files)
+}
+
+/// Fails if the canonical `output_path` lies inside an input directory, where
+/// a later run, or one with different patterns, could pick it up again.
+fn check_output_outside_roots(options: &ConcatOptions, output_path: &Path) -> io::Result<()> {
+    for directory in &options.directories {
+        if output_path.starts_with(fs::canonicalize(directory)?) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "output {} is inside input directory {}; pass --allow-output-in-tree to write it there anyway",
+                    output_path.display(),
+                    directory.display()
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Recognizes the output file during traversal without canonicalizing every path.
+struct OutputGuard<'a> {
+    /// Canonical path of the output file.
+    output_path: &'a Path,
+}
+
+impl<'a> OutputGuard<'a> {
+    /// Returns `None` when the output can't be reached from any input root,
+    /// in which case no file needs checking at all.
+    fn new(options: &ConcatOptions, output_path: &'a Path) -> io::Result<Option<Self>> {
+        // Followed symlinks may lead anywhere, so the roots alone don't tell us.
+        if !options.follow_symlinks {
+            let roots = options
+                .directories
+                .iter()
+                .map(fs::canonicalize)
+                .collect::<io::Result<Vec<_>>>()?;
+            if !roots.iter().any(|root| output_path.starts_with(root)) {
+                return Ok(None);
+            }
+        }
+        Ok(Some(OutputGuard { output_path }))
+    }
+
+    /// Cheap check: only a file with the output's name, or a symlink that may
+    /// point at it under another name, can be the output.
+    fn may_be_output(&self, path: &Path) -> bool {
+        path.file_name() == self.output_path.file_name() || path.is_symlink()
+    }
+
+    fn is_output(&self, path: &Path) -> io::Result<bool> {
+        Ok(self.may_be_output(path) && fs::canonicalize(path)? == self.output_path)
+    }
+}
+
+/// Stands in for `read_file` with `--no-content`, only reading the file if
+/// `--checksums` needs it.
+fn leave_out_contents(options: &ConcatOptions, file: &CollectedFile) -> io::Result<FileContents> {
+    let checksum = if options.checksums {
+        Some(content::short_checksum(&fs::read(&file.path)?))
+    } else {
+        None
+    };
+    Ok(FileContents::Included(Vec::new(), checksum))
+}
+
+/// Reads and transforms a single file's contents, unless it is skipped.
+fn read_file(options: &ConcatOptions, file: &CollectedFile) -> io::Result<FileContents> {
+    if options.max_file_size.is_some_and(|max| file.len > max) {
+        return Ok(FileContents::Skipped(format!(
+            "skipped: {} bytes exceeds limit",
+            file.len
+        )));
+    }
+    if options.skip_binary && binary::is_binary(&file.path)? {
+        return Ok(FileContents::Skipped("skipped binary file".to_string()));
+    }
+    if options.binary_as_base64 && binary::is_binary(&file.path)? {
+        let contents = fs::read(&file.path)?;
+        let checksum = options
+            .checksums
+            .then(|| content::short_checksum(&contents));
+        let mut encoded = Vec::new();
+        let marker = binary::base64_marker(contents.len());
+        write_comment(&mut encoded, options, &file.path, &marker)?;
+        encoded.extend_from_slice(&binary::to_base64_lines(&contents));
+        return Ok(FileContents::Included(encoded, checksum));
+    }
+
+    let mut contents = fs::read(&file.path)?;
+    let checksum = options
+        .checksums
+        .then(|| content::short_checksum(&contents));
+    match options.encoding {
+        Encoding::Raw => {}
+        Encoding::Utf8Strict => {
+            if std::str::from_utf8(&contents).is_err() {
+                warn(
+                    options,
+                    format_args!("skipping {}, which is not valid UTF-8", file.path.display()),
+                );
+                return Ok(FileContents::Skipped(
+                    "skipped: not valid UTF-8".to_string(),
+                ));
+            }
+        }
+        Encoding::Utf8Lossy => contents = content::to_utf8_lossy(contents),
+    }
+    if !options.replace.is_empty() {
+        contents = content::replace_all(contents, &options.replace);
+    }
+    // The marker is added once the contents are transformed, so they leave it alone.
+    let cut = match options.truncate_file {
+        Some(max) => content::truncate(&mut contents, max),
+        None => 0,
+    };
+    if options.strip_comments {
+        if let Some(syntax) = comments::syntax_for(&file.path) {
+            contents = comments::strip_comments(&contents, syntax);
+        }
+    }
+    if !content::whitespace_sensitive(&file.path) {
+        if options.trim_trailing {
+            contents = content::trim_trailing(&contents);
+        }
+        if let Some(width) = options.tabs_to_spaces {
+            contents = content::tabs_to_spaces(&contents, width);
+        }
+        if let Some(width) = options.spaces_to_tabs {
+            contents = content::spaces_to_tabs(&contents, width);
+        }
+    }
+    if options.squeeze_blank {
+        contents = content::squeeze_blank(&contents);
+    }
+    if options.indent_by_depth {
+        contents = content::indent_lines(&contents, file.depth * options.indent_width);
+    }
+    if options.line_numbers {
+        contents = content::number_lines(&contents);
+    }
+    if options.head.is_some() || options.tail.is_some() {
+        let head = options.head.unwrap_or(0);
+        let tail = options.tail.unwrap_or(0);
+        if let Some((head, omitted, tail)) = content::head_and_tail(&contents, head, tail) {
+            let mut sliced = head.to_vec();
+            let marker = format!("[{} lines omitted]", omitted);
+            write_comment(&mut sliced, options, &file.path, &marker)?;
+            sliced.extend_from_slice(tail);
+            contents = sliced;
+        }
+    }
+    if cut > 0 {
+        if !contents.ends_with(b"\n") {
+            contents.push(b'\n');
+        }
+        let marker = format!("[truncated, {} more bytes]", cut);
+        write_comment(&mut contents, options, &file.path, &marker)?;
+    }
+    contents = content::normalize_line_endings(contents, options.line_endings);
+    Ok(FileContents::Included(contents, checksum))
+}
+
+/// State threaded through the recursive walk in `visit_dirs`.
+#[derive(Default)]
+struct VisitState {
+    gitignores: GitignoreStack,
+    /// Canonical paths of directories already walked, to break symlink cycles.
+    visited: HashSet<PathBuf>,
+}
+
+/// Whether a directory named `name` is skipped without reading it, by
+/// `--exclude-dir` or `--prune-defaults`.
+fn is_pruned(options: &ConcatOptions, name: &std::ffi::OsStr) -> bool {
+    options
+        .exclude_dir
+        .iter()
+        .any(|excluded| name == excluded.as_str())
+        || (options.prune_defaults && PRUNE_DEFAULTS.iter().any(|&pruned| name == pruned))
+}
+
+/// Calls `cb` with every file under `dir`, which sits `depth` directories below
+/// its input root, and that depth. Files directly in the root are at depth 0, so subdirectories
+/// are only entered while their contents would be within `--max-depth`, and
+/// files shallower than `--min-depth` are passed over.
+///
+/// The walk stops as soon as `cb` breaks, and the break is passed on to the caller.
+fn visit_dirs<F>(
+    dir: &Path,
+    options: &ConcatOptions,
+    cb: &mut F,
+    depth: usize,
+    state: &mut VisitState,
+) -> io::Result<ControlFlow<()>>
+where
+    F: FnMut(&DirEntry, usize) -> io::Result<ControlFlow<()>>,
+{
+    match options.traversal {
+        Traversal::Dfs => visit_depth_first(dir, options, cb, depth, state),
+        Traversal::Bfs => visit_breadth_first(dir, options, cb, depth, state),
+    }
+}
+
+fn visit_depth_first<F>(
+    dir: &Path,
+    options: &ConcatOptions,
+    cb: &mut F,
+    depth: usize,
+    state: &mut VisitState,
+) -> io::Result<ControlFlow<()>>
+where
+    F: FnMut(&DirEntry, usize) -> io::Result<ControlFlow<()>>,
+{
+    visit_dir(dir, options, cb, depth, state, &mut |path, cb, state| {
+        visit_depth_first(path, options, cb, depth + 1, state)
+    })
+}
+
+/// Warns that the directory `dir`, `depth` levels down, couldn't be read and
+/// lets the walk carry on without it. An input directory itself, or any
+/// directory with `--fail-fast`, is an error instead.
+fn skip_unreadable_dir(
+    options: &ConcatOptions,
+    dir: &Path,
+    depth: usize,
+    error: io::Error,
+) -> io::Result<ControlFlow<()>> {
+    if depth == 0 || options.fail_fast {
+        return Err(error);
+    }
+    warn(
+        options,
+        format_args!("skipping {}: {}", dir.display(), error),
+    );
+    Ok(ControlFlow::Continue(()))
+}
+
+/// Walks a queue of directories, each with the `.gitignore` stack it was
+/// found under, so a level is finished before the next is read.
+fn visit_breadth_first<F>(
+    dir: &Path,
+    options: &ConcatOptions,
+    cb: &mut F,
+    depth: usize,
+    state: &mut VisitState,
+) -> io::Result<ControlFlow<()>>
+where
+    F: FnMut(&DirEntry, usize) -> io::Result<ControlFlow<()>>,
+{
+    let mut queue = VecDeque::from([(dir.to_path_buf(), depth, state.gitignores.clone())]);
+    while let Some((dir, depth, gitignores)) = queue.pop_front() {
+        state.gitignores = gitignores;
+        let flow = visit_dir(&dir, options, cb, depth, state, &mut |path, _, state| {
+            queue.push_back((path.to_path_buf(), depth + 1, state.gitignores.clone()));
+            Ok(ControlFlow::Continue(()))
+        })?;
+        if flow.is_break() {
+            return Ok(flow);
+        }
+    }
+    Ok(ControlFlow::Continue(()))
+}
+
+/// Reads the entries of `dir`, passing its files to `cb` and the
+/// subdirectories to walk to `enter`, in sorted order.
+fn visit_dir<F, G>(
+    dir: &Path,
+    options: &ConcatOptions,
+    cb: &mut F,
+    depth: usize,
+    state: &mut VisitState,
+    enter: &mut G,
+) -> io::Result<ControlFlow<()>>
+where
+    F: FnMut(&DirEntry, usize) -> io::Result<ControlFlow<()>>,
+    G: FnMut(&Path, &mut F, &mut VisitState) -> io::Result<ControlFlow<()>>,
+{
+    let mut flow = ControlFlow::Continue(());
+
+    if dir.is_dir() {
+        let canonical = match fs::canonicalize(dir) {
+            Ok(canonical) => canonical,
+            Err(error) => return skip_unreadable_dir(options, dir, depth, error),
+        };
+        if !state.visited.insert(canonical) {
+            return Ok(flow);
+        }
+
+        // Read before the `.gitignore` is pushed, so there's nothing to pop if it fails.
+        let mut entries = match fs::read_dir(dir).and_then(Iterator::collect::<io::Result<Vec<_>>>)
+        {
+            Ok(entries) => entries,
+            Err(error) => return skip_unreadable_dir(options, dir, depth, error),
+        };
+        if options.verbose > 1 {
+            eprintln!("entering {}", dir.display());
+        }
+        let pushed = options.respect_gitignore && state.gitignores.push_dir(dir)?;
+        sort::sort_entries(&mut entries, options.sort);
+
+        for entry in entries {
+            let path = entry.path();
+            if !options.hidden && entry.file_name().as_encoded_bytes().starts_with(b".") {
+                explain_exclusion(options, &path, Exclusion::Hidden);
+                continue;
+            }
+
+            let is_dir = path.is_dir();
+            if let Some(rule) = state.gitignores.ignoring_rule(&path, is_dir) {
+                explain_exclusion(options, &path, Exclusion::Gitignored(rule));
+                continue;
+            }
+            // `DirEntry::file_type` doesn't follow links, unlike `is_dir` above.
+            if !options.follow_symlinks && entry.file_type()?.is_symlink() {
+                explain_exclusion(options, &path, Exclusion::Symlink);
+                continue;
+            }
+
+            if is_dir {
+                let exclusion = if options.no_recursive {
+                    Some(Exclusion::NotRecursive)
+                } else if is_pruned(options, &entry.file_name()) {
+                    Some(Exclusion::ExcludedDir)
+                } else if depth >= options.max_depth {
+                    Some(Exclusion::MaxDepth)
+                } else {
+                    None
+                };
+                if let Some(exclusion) = exclusion {
+                    explain_exclusion(options, &path, exclusion);
+                    continue;
+                }
+                flow = enter(&path, cb, state)?;
+            } else if depth >= options.min_depth {
+                flow = cb(&entry, depth)?;
+            } else {
+                explain_exclusion(options, &path, Exclusion::MinDepth);
+            }
+            if flow.is_break() {
+                break;
+            }
+        }
+
+        if pushed {
+            state.gitignores.pop();
+        }
+    }
+
+    Ok(flow)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsStr;
+    use std::fs::File;
+    use tempfile::TempDir;
+
+    fn create_test_directory() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path();
+
+        fs::write(path.join("file1.txt"), "Content of file1").unwrap();
+        fs::write(path.join("file2.ts"), "Content of file2").unwrap();
+        fs::create_dir(path.join("subdir")).unwrap();
+        fs::write(path.join("subdir").join("file3.ts"), "Content of file3").unwrap();
+        fs::create_dir(path.join("node_modules")).unwrap();
+        fs::write(
+            path.join("node_modules").join("file4.ts"),
+            "Content of file4",
+        )
+        .unwrap();
+
+        dir
+    }
+
+    /// Selects and writes the files for `options`, as a run without `--output` would.
+    fn write_selected<W: Write>(options: &ConcatOptions, writer: W) -> io::Result<Stats> {
+        let files = select_files(options, None)?;
+        write_files(options, writer, &files, &options.directories)
+    }
+
+    fn test_options(directory: &Path) -> ConcatOptions {
+        ConcatOptions::parse_from([
+            OsStr::new("concacti"),
+            OsStr::new("-d"),
+            directory.as_os_str(),
+        ])
+    }
+
+    /// Concatenates into `output_file`, as a run with `--output` and
+    /// `--allow-output-in-tree` would, and returns what was written.
+    fn concatenate_to_file(options: &ConcatOptions, output_file: &Path) -> String {
+        let file = File::create(output_file).unwrap();
+        let mut concatenator = Concatenator::new(ConcatOptions {
+            allow_output_in_tree: true,
+            ..options.clone()
+        });
+        concatenator.exclude_output(output_file).unwrap();
+        concatenator.concatenate(file).unwrap();
+        fs::read_to_string(output_file).unwrap()
+    }
+
+    #[test]
+    fn test_wildcard_include() {
+        let temp_dir = create_test_directory();
+        let output_file = temp_dir.path().join("output.txt");
+
+        let options = ConcatOptions {
+            patterns: vec!["**/*.ts".to_string()],
+            max_depth: usize::MAX,
+            write_filenames: false,
+            write_tree: false,
+            comment_style: "//".to_string(),
+            ..test_options(temp_dir.path())
+        };
+
+        let output_content = concatenate_to_file(&options, &output_file);
+
+        assert!(!output_content.contains("Content of file1"));
+        assert!(output_content.contains("Content of file2"));
+        assert!(output_content.contains("Content of file3"));
+        assert!(output_content.contains("Content of file4"));
+    }
+
+    #[test]
+    fn test_wildcard_exclude() {
+        let temp_dir = create_test_directory();
+        let output_file = temp_dir.path().join("output.txt");
+
+        let options = ConcatOptions {
+            patterns: vec!["**/*.ts".to_string(), "!**/node_modules/**".to_string()],
+            max_depth: usize::MAX,
+            write_filenames: false,
+            write_tree: false,
+            comment_style: "//".to_string(),
+            ..test_options(temp_dir.path())
+        };
+
+        let output_content = concatenate_to_file(&options, &output_file);
+
+        assert!(!output_content.contains("Content of file1"));
+        assert!(output_content.contains("Content of file2"));
+        assert!(output_content.contains("Content of file3"));
+        assert!(!output_content.contains("Content of file4"));
+    }
+
+    #[test]
+    fn test_multiple_patterns() {
+        let temp_dir = create_test_directory();
+        let output_file = temp_dir.path().join("output.txt");
+
+        let options = ConcatOptions {
+            patterns: vec![
+                "**/*.ts".to_string(),
+                "**/*.txt".to_string(),
+                "!**/node_modules/**".to_string(),
+            ],
+            max_depth: usize::MAX,
+            write_filenames: false,
+            write_tree: false,
+            comment_style: "//".to_string(),
+            ..test_options(temp_dir.path())
+        };
+
+        let output_content = concatenate_to_file(&options, &output_file);
+
+        assert!(output_content.contains("Content of file1"));
+        assert!(output_content.contains("Content of file2"));
+        assert!(output_content.contains("Content of file3"));
+        assert!(!output_content.contains("Content of file4"));
+    }
+
+    #[test]
+    fn test_no_patterns() {
+        let temp_dir = create_test_directory();
+        let output_file = temp_dir.path().join("output.txt");
+
+        let options = ConcatOptions {
+            patterns: vec![],
+            max_depth: usize::MAX,
+            write_filenames: false,
+            write_tree: false,
+            comment_style: "//".to_string(),
+            ..test_options(temp_dir.path())
+        };
+
+        let output_content = concatenate_to_file(&options, &output_file);
+
+        assert!(output_content.contains("Content of file1"));
+        assert!(output_content.contains("Content of file2"));
+        assert!(output_content.contains("Content of file3"));
+        assert!(output_content.contains("Content of file4"));
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let temp_dir = create_test_directory();
+        let output_file = temp_dir.path().join("output.txt");
+
+        let options = ConcatOptions {
+            patterns: vec!["**/*.ts".to_string()],
+            max_depth: 0,
+            write_filenames: false,
+            write_tree: false,
+            comment_style: "//".to_string(),
+            ..test_options(temp_dir.path())
+        };
+
+        let output_content = concatenate_to_file(&options, &output_file);
+
+        assert!(output_content.contains("Content of file2"));
+        assert!(!output_content.contains("Content of file3"));
+        assert!(!output_content.contains("Content of file4"));
+    }
+
+    /// A tree with one file at each of depths 0, 1 and 2.
+    fn create_nested_directory() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path();
+        fs::create_dir_all(path.join("a").join("b")).unwrap();
+        fs::write(path.join("root.txt"), "").unwrap();
+        fs::write(path.join("a").join("one.txt"), "").unwrap();
+        fs::write(path.join("a").join("b").join("two.txt"), "").unwrap();
+        dir
+    }
+
+    /// File names `collect_files` selects for `options`, in order.
+    fn collected_names(options: &ConcatOptions) -> Vec<String> {
+        let file_filter = build_file_filter(options).unwrap();
+        collect_files(options, &file_filter, None, &mut Vec::new())
+            .unwrap()
+            .iter()
+            .map(|file| {
+                file.path
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_exclude_dir() {
+        let temp_dir = create_test_directory();
+        fs::create_dir_all(temp_dir.path().join("target").join("debug")).unwrap();
+        fs::write(
+            temp_dir.path().join("target").join("debug").join("out.ts"),
+            "",
+        )
+        .unwrap();
+
+        let names = |options: &ConcatOptions| {
+            let files = select_files(options, None).unwrap();
+            files
+                .iter()
+                .map(|file| file.path.file_name().unwrap().to_str().unwrap().to_owned())
+                .collect::<Vec<_>>()
+        };
+        let options = ConcatOptions {
+            patterns: vec!["**/*.ts".to_string()],
+            exclude_dir: vec!["node_modules".to_string(), "debug".to_string()],
+            ..test_options(temp_dir.path())
+        };
+        assert_eq!(names(&options), ["file2.ts", "file3.ts"]);
+
+        let options = ConcatOptions {
+            exclude_dir: Vec::new(),
+            prune_defaults: true,
+            ..options
+        };
+        assert_eq!(names(&options), ["file2.ts", "file3.ts"]);
+
+        let mut output = Vec::new();
+        write_selected(
+            &ConcatOptions {
+                write_tree: true,
+                ..options
+            },
+            &mut output,
+        )
+        .unwrap();
+        assert!(!String::from_utf8(output).unwrap().contains("node_modules"));
+    }
+
+    #[test]
+    fn test_skip_lockfiles() {
+        let temp_dir = create_test_directory();
+        fs::write(temp_dir.path().join("Cargo.lock"), "").unwrap();
+        fs::write(temp_dir.path().join("subdir").join("app.min.js"), "").unwrap();
+        fs::write(temp_dir.path().join("subdir").join("app.js"), "").unwrap();
+
+        let options = ConcatOptions {
+            patterns: vec!["!**/node_modules/**".to_string()],
+            skip_lockfiles: true,
+            ..test_options(temp_dir.path())
+        };
+        let files = select_files(&options, None).unwrap();
+        let names: Vec<_> = files
+            .iter()
+            .map(|file| file.path.file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(names, ["file1.txt", "file2.ts", "app.js", "file3.ts"]);
+    }
+
+    #[test]
+    fn test_max_depth_levels() {
+        let temp_dir = create_nested_directory();
+        let names_at = |max_depth| {
+            collected_names(&ConcatOptions {
+                max_depth,
+                ..test_options(temp_dir.path())
+            })
+        };
+
+        assert_eq!(names_at(0), ["root.txt"]);
+        assert_eq!(names_at(1), ["one.txt", "root.txt"]);
+        assert_eq!(names_at(2), ["two.txt", "one.txt", "root.txt"]);
+    }
+
+    #[test]
+    fn test_no_recursive() {
+        let temp_dir = create_test_directory();
+        let options = ConcatOptions {
+            no_recursive: true,
+            ..test_options(temp_dir.path())
+        };
+        assert_eq!(collected_names(&options), ["file1.txt", "file2.ts"]);
+    }
+
+    #[test]
+    fn test_limit() {
+        let temp_dir = create_test_directory();
+        let names_with_limit = |limit| {
+            collected_names(&ConcatOptions {
+                limit: Some(limit),
+                sort: SortMode::Name,
+                ..test_options(temp_dir.path())
+            })
+        };
+
+        assert!(names_with_limit(0).is_empty());
+        assert_eq!(names_with_limit(2), ["file1.txt", "file2.ts"]);
+        assert_eq!(names_with_limit(3), ["file1.txt", "file2.ts", "file4.ts"]);
+        assert_eq!(names_with_limit(10).len(), 4);
+    }
+
+    #[test]
+    fn test_exclude_empty() {
+        let temp_dir = create_test_directory();
+        fs::write(temp_dir.path().join("__init__.py"), "").unwrap();
+
+        let options = ConcatOptions {
+            no_recursive: true,
+            ..test_options(temp_dir.path())
+        };
+        assert_eq!(
+            collected_names(&options),
+            ["__init__.py", "file1.txt", "file2.ts"]
+        );
+
+        let options = ConcatOptions {
+            exclude_empty: true,
+            ..options
+        };
+        assert_eq!(collected_names(&options), ["file1.txt", "file2.ts"]);
+    }
+
+    #[test]
+    fn test_min_depth() {
+        let temp_dir = create_nested_directory();
+        let names_between = |min_depth, max_depth| {
+            collected_names(&ConcatOptions {
+                min_depth,
+                max_depth,
+                ..test_options(temp_dir.path())
+            })
+        };
+
+        assert_eq!(names_between(1, usize::MAX), ["two.txt", "one.txt"]);
+        assert_eq!(names_between(1, 1), ["one.txt"]);
+        assert!(names_between(2, 1).is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unreadable_subdirectory() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = create_nested_directory();
+        let locked = temp_dir.path().join("locked");
+        fs::create_dir(&locked).unwrap();
+        fs::write(locked.join("secret.txt"), "").unwrap();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+
+        let readable = fs::read_dir(&locked).is_ok();
+        let options = test_options(temp_dir.path());
+        let names = collected_names(&options);
+        let file_filter = build_file_filter(&options).unwrap();
+        let fail_fast = ConcatOptions {
+            fail_fast: true,
+            ..options
+        };
+        let fail_fast = collect_files(&fail_fast, &file_filter, None, &mut Vec::new());
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+
+        assert!(names.contains(&"two.txt".to_string()));
+        // Permissions aren't enforced when running as root.
+        if !readable {
+            assert!(!names.contains(&"secret.txt".to_string()));
+            assert!(fail_fast.is_err());
+        }
+    }
+
+    #[test]
+    fn test_comment_style() {
+        let temp_dir = create_test_directory();
+        let output_file = temp_dir.path().join("output.txt");
+
+        let options = ConcatOptions {
+            patterns: vec!["**/*.ts".to_string()],
+            max_depth: usize::MAX,
+            write_filenames: true,
+            write_tree: false,
+            comment_style: "#".to_string(),
+            ..test_options(temp_dir.path())
+        };
+
+        let output_content = concatenate_to_file(&options, &output_file);
+
+        assert!(output_content.contains("# "));
+        assert!(!output_content.contains("// "));
+    }
+
+    #[test]
+    fn test_write_filenames() {
+        let temp_dir = create_test_directory();
+        let output_file = temp_dir.path().join("output.txt");
+
+        let options = ConcatOptions {
+            patterns: vec!["**/*.ts".to_string()],
+            max_depth: usize::MAX,
+            write_filenames: true,
+            write_tree: false,
+            comment_style: "//".to_string(),
+            ..test_options(temp_dir.path())
+        };
+
+        let output_content = concatenate_to_file(&options, &output_file);
+
+        assert!(output_content.contains("// "));
+        assert!(output_content.contains("file2.ts"));
+        assert!(output_content.contains("file3.ts"));
+    }
+
+    #[test]
+    fn test_write_tree() {
+        let temp_dir = create_test_directory();
+        let output_file = temp_dir.path().join("output.txt");
+
+        let options = ConcatOptions {
+            patterns: vec!["**/*.ts".to_string()],
+            max_depth: usize::MAX,
+            write_filenames: false,
+            write_tree: true,
+            comment_style: "//".to_string(),
+            ..test_options(temp_dir.path())
+        };
+
+        let output_content = concatenate_to_file(&options, &output_file);
+
+        assert!(output_content.contains("subdir"));
+        assert!(output_content.contains("node_modules"));
+        assert!(output_content.contains("file2.ts"));
+        assert!(output_content.contains("file3.ts"));
+        assert!(output_content.contains("file4.ts"));
+    }
+
+    #[test]
+    fn test_stdout_writer() {
+        let temp_dir = create_test_directory();
+
+        let options = ConcatOptions {
+            patterns: vec!["**/*.ts".to_string(), "!**/node_modules/**".to_string()],
+            write_filenames: false,
+            write_tree: false,
+            ..test_options(temp_dir.path())
+        };
+
+        let mut output = Vec::new();
+        write_selected(&options, &mut output).unwrap();
+
+        let output_content = String::from_utf8(output).unwrap();
+        assert!(output_content.contains("Content of file2"));
+        assert!(output_content.contains("Content of file3"));
+        assert!(!output_content.contains("Content of file4"));
+        assert!(!temp_dir.path().join("-").exists());
+    }
+
+    #[test]
+    fn test_respect_gitignore() {
+        let temp_dir = create_test_directory();
+        let output_file = temp_dir.path().join("output.txt");
+        fs::write(temp_dir.path().join(".gitignore"), "node_modules/\n").unwrap();
+        fs::write(temp_dir.path().join("subdir").join(".gitignore"), "*.txt\n").unwrap();
+        fs::write(
+            temp_dir.path().join("subdir").join("notes.txt"),
+            "Subdir notes",
+        )
+        .unwrap();
+
+        let options = ConcatOptions {
+            patterns: vec!["**/*.ts".to_string(), "**/*.txt".to_string()],
+            write_filenames: false,
+            write_tree: false,
+            respect_gitignore: true,
+            ..test_options(temp_dir.path())
+        };
+
+        let output_content = concatenate_to_file(&options, &output_file);
+
+        assert!(output_content.contains("Content of file1"));
+        assert!(output_content.contains("Content of file2"));
+        assert!(output_content.contains("Content of file3"));
+        assert!(!output_content.contains("Content of file4"));
+        assert!(!output_content.contains("Subdir notes"));
+    }
+
+    #[test]
+    fn test_skip_binary() {
+        let temp_dir = create_test_directory();
+        let output_file = temp_dir.path().join("output.txt");
+        fs::write(temp_dir.path().join("image.ts"), b"Binary\0content").unwrap();
+
+        let options = ConcatOptions {
+            patterns: vec!["**/*.ts".to_string()],
+            write_filenames: true,
+            write_tree: false,
+            skip_binary: true,
+            ..test_options(temp_dir.path())
+        };
+
+        let output_content = concatenate_to_file(&options, &output_file);
+
+        assert!(output_content.contains("Content of file2"));
+        assert!(output_content.contains("// [skipped binary file]"));
+        assert!(output_content.contains("image.ts"));
+        assert!(!output_content.contains("Binary"));
+    }
+
+    #[test]
+    fn test_binary_as_base64() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("image.bin"), b"Binary\0content").unwrap();
+
+        let options = ConcatOptions {
+            write_tree: false,
+            binary_as_base64: true,
+            ..test_options(temp_dir.path())
+        };
+
+        let mut output = Vec::new();
+        write_selected(&options, &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("image.bin\n// [base64, 14 bytes]\nQmluYXJ5AGNvbnRlbnQ=\n"));
+    }
+
+    #[test]
+    fn test_ensure_trailing_newline() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("a.txt"), "with newline\n").unwrap();
+        fs::write(temp_dir.path().join("b.txt"), "without newline").unwrap();
+
+        let options = ConcatOptions {
+            write_filenames: false,
+            write_tree: false,
+            ensure_trailing_newline: true,
+            ..test_options(temp_dir.path())
+        };
+
+        let mut output = Vec::new();
+        write_selected(&options, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "with newline\nwithout newline\n"
+        );
+    }
+
+    #[test]
+    fn test_encoding() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("bad.txt"), b"caf\xe9\n").unwrap();
+        fs::write(temp_dir.path().join("good.txt"), "café\n").unwrap();
+
+        let output_with = |encoding| {
+            let options = ConcatOptions {
+                write_tree: false,
+                encoding,
+                ..test_options(temp_dir.path())
+            };
+            let mut output = Vec::new();
+            write_selected(&options, &mut output).unwrap();
+            output
+        };
+
+        let raw = output_with(Encoding::Raw);
+        assert!(raw.windows(4).any(|window| window == b"caf\xe9"));
+
+        let strict = String::from_utf8(output_with(Encoding::Utf8Strict)).unwrap();
+        assert!(strict.contains("[skipped: not valid UTF-8]"));
+        assert!(strict.contains("café\n"));
+
+        let lossy = String::from_utf8(output_with(Encoding::Utf8Lossy)).unwrap();
+        assert!(lossy.contains("caf\u{FFFD}\n"));
+    }
+
+    #[test]
+    fn test_indent_by_depth() {
+        let temp_dir = create_nested_directory();
+        fs::write(temp_dir.path().join("root.txt"), "root\n").unwrap();
+        fs::write(temp_dir.path().join("a").join("b").join("two.txt"), "two\n").unwrap();
+
+        let options = ConcatOptions {
+            patterns: vec!["**/root.txt".to_string(), "**/two.txt".to_string()],
+            write_filenames: false,
+            write_tree: false,
+            indent_by_depth: true,
+            indent_width: 3,
+            line_numbers: true,
+            ..test_options(temp_dir.path())
+        };
+
+        let mut output = Vec::new();
+        write_selected(&options, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "1 |       two\n\n1 | root\n\n"
+        );
+    }
+
+    #[test]
+    fn test_line_numbers() {
+        let temp_dir = create_test_directory();
+        let output_file = temp_dir.path().join("output.txt");
+        fs::write(temp_dir.path().join("lines.ts"), "first\nsecond").unwrap();
+
+        let options = ConcatOptions {
+            patterns: vec!["**/lines.ts".to_string()],
+            write_filenames: false,
+            write_tree: false,
+            line_numbers: true,
+            ..test_options(temp_dir.path())
+        };
+
+        let output_content = concatenate_to_file(&options, &output_file);
+
+        assert_eq!(output_content, "1 | first\n2 | second\n");
+    }
+
+    #[test]
+    fn test_jobs_preserve_order() {
+        let temp_dir = create_test_directory();
+        for i in 0..32 {
+            fs::write(
+                temp_dir.path().join("subdir").join(format!("many{}.ts", i)),
+                format!("Content of many{}", i),
+            )
+            .unwrap();
+        }
+
+        let mut outputs = Vec::new();
+        for jobs in [1, 4] {
+            let options = ConcatOptions {
+                patterns: vec!["**/*.ts".to_string()],
+                write_filenames: true,
+                write_tree: false,
+                jobs: Some(jobs),
+                ..test_options(temp_dir.path())
+            };
+
+            let mut output = Vec::new();
+            write_selected(&options, &mut output).unwrap();
+            outputs.push(String::from_utf8(output).unwrap());
+        }
+
+        assert!(outputs[0].contains("Content of many31"));
+        assert_eq!(outputs[0], outputs[1]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_cycle() {
+        let temp_dir = create_test_directory();
+        let subdir = temp_dir.path().join("subdir");
+        std::os::unix::fs::symlink(temp_dir.path(), subdir.join("loop")).unwrap();
+        std::os::unix::fs::symlink(&subdir, temp_dir.path().join("linked")).unwrap();
+
+        for follow_symlinks in [false, true] {
+            let options = ConcatOptions {
+                patterns: vec!["**/file3.ts".to_string()],
+                write_filenames: false,
+                write_tree: false,
+                follow_symlinks,
+                ..test_options(temp_dir.path())
+            };
+
+            let mut output = Vec::new();
+            write_selected(&options, &mut output).unwrap();
+
+            let output_content = String::from_utf8(output).unwrap();
+            assert_eq!(output_content.matches("Content of file3").count(), 1);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinked_files() {
+        let temp_dir = create_test_directory();
+        let output_file = temp_dir.path().join("output.txt");
+        fs::write(&output_file, "Previous output").unwrap();
+        std::os::unix::fs::symlink(
+            temp_dir.path().join("file1.txt"),
+            temp_dir.path().join("linked.txt"),
+        )
+        .unwrap();
+        std::os::unix::fs::symlink(&output_file, temp_dir.path().join("to_output.txt")).unwrap();
+
+        for follow_symlinks in [false, true] {
+            let options = ConcatOptions {
+                patterns: vec!["*.txt".to_string()],
+                follow_symlinks,
+                allow_output_in_tree: true,
+                ..test_options(temp_dir.path())
+            };
+            let output_path = fs::canonicalize(&output_file).unwrap();
+            let files = select_files(&options, Some(&output_path)).unwrap();
+            let names: Vec<_> = files
+                .iter()
+                .map(|file| file.path.file_name().unwrap().to_str().unwrap())
+                .collect();
+
+            if follow_symlinks {
+                assert_eq!(names, ["file1.txt", "linked.txt"]);
+            } else {
+                assert_eq!(names, ["file1.txt"]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_markdown_format() {
+        let temp_dir = create_test_directory();
+        fs::write(
+            temp_dir.path().join("fenced.md"),
+            "```rust\nlet a = 1;\n```\n",
+        )
+        .unwrap();
+
+        let options = ConcatOptions {
+            patterns: vec!["**/file2.ts".to_string(), "**/fenced.md".to_string()],
+            write_tree: false,
+            format: OutputFormat::Markdown,
+            ..test_options(temp_dir.path())
+        };
+
+        let mut output = Vec::new();
+        write_selected(&options, &mut output).unwrap();
+
+        let output_content = String::from_utf8(output).unwrap();
+        assert!(output_content.contains("file2.ts\n\n```ts\nContent of file2\n```\n"));
+        assert!(output_content.contains("fenced.md\n\n````markdown\n```rust\n"));
+        assert!(output_content.contains("### "));
+        assert!(!output_content.contains("// "));
+    }
+
+    #[test]
+    fn test_toc() {
+        let temp_dir = create_test_directory();
+        let options = ConcatOptions {
+            patterns: vec!["**/*.ts".to_string(), "!**/node_modules/**".to_string()],
+            toc: true,
+            relative_paths: true,
+            ..test_options(temp_dir.path())
+        };
+
+        let mut output = Vec::new();
+        let stats = write_selected(&options, &mut output).unwrap();
+
+        let output_content = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output_content.lines().collect();
+        let toc_start = lines
+            .iter()
+            .position(|line| *line == "// Contents")
+            .unwrap();
+        // The tree comes first, and the table lists files in output order.
+        assert!(toc_start > 0);
+        for (index, name) in ["file2.ts", "subdir/file3.ts"].iter().enumerate() {
+            let entry = lines[toc_start + 1 + index];
+            let prefix = format!("// {}. {} (line ", index + 1, name);
+            let line: usize = entry
+                .strip_prefix(&prefix)
+                .and_then(|rest| rest.strip_suffix(')'))
+                .unwrap()
+                .parse()
+                .unwrap();
+            assert!(lines[line - 1].ends_with(name), "{:?}", lines[line - 1]);
+
+            let included = &stats.included[index];
+            let start = included.offset as usize;
+            let entry = &output_content[start..start + included.length as usize];
+            assert!(entry.starts_with(&format!("// {}\n", name)), "{:?}", entry);
+        }
+    }
+
+    #[test]
+    fn test_group_by_dir() {
+        let temp_dir = create_test_directory();
+        fs::write(
+            temp_dir.path().join("subdir").join("file5.ts"),
+            "Content of file5",
+        )
+        .unwrap();
+        let options = ConcatOptions {
+            patterns: vec!["**/*.ts".to_string(), "!**/node_modules/**".to_string()],
+            write_tree: false,
+            relative_paths: true,
+            group_by_dir: true,
+            ..test_options(temp_dir.path())
+        };
+
+        let mut output = Vec::new();
+        write_selected(&options, &mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "// === ./ ===\n// file2.ts\nContent of file2\n\
+             // === subdir/ ===\n// subdir/file3.ts\nContent of file3\n\
+             // subdir/file5.ts\nContent of file5\n"
+        );
+    }
+
+    #[test]
+    fn test_header() {
+        let temp_dir = create_test_directory();
+        let header_file = temp_dir.path().join("header.txt");
+        fs::write(&header_file, "Files: {file_count}").unwrap();
+
+        let options = ConcatOptions {
+            patterns: vec!["**/*.ts".to_string()],
+            header_file: Some(header_file),
+            ..test_options(temp_dir.path())
+        };
+
+        let mut output = Vec::new();
+        write_selected(&options, &mut output).unwrap();
+
+        let output_content = String::from_utf8(output).unwrap();
+        assert!(output_content.starts_with("Files: 3\n"));
+        assert!(output_content.contains("Content of file2"));
+    }
+
+    #[test]
+    fn test_json_format() {
+        let temp_dir = create_test_directory();
+        fs::write(temp_dir.path().join("quoted.ts"), "say(\"hi\")\n").unwrap();
+
+        let options = ConcatOptions {
+            patterns: vec!["**/file2.ts".to_string(), "**/quoted.ts".to_string()],
+            filtered_tree: true,
+            relative_paths: true,
+            format: OutputFormat::Json,
+            summary: true,
+            separator: Some("----".to_string()),
+            ..test_options(temp_dir.path())
+        };
+
+        let mut output = Vec::new();
+        write_selected(&options, &mut output).unwrap();
+
+        let value: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        let files = value["files"].as_array().unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0]["path"], "file2.ts");
+        assert_eq!(files[0]["size"], 16);
+        assert_eq!(files[0]["content"], "Content of file2");
+        assert_eq!(files[1]["content"], "say(\"hi\")\n");
+        assert_eq!(value["tree"][0]["children"][1]["name"], "quoted.ts");
+        assert_eq!(value["summary"]["files"], 2);
+    }
+
+    #[test]
+    fn test_line_endings() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("a.txt"), "one\r\ntwo\n").unwrap();
+        fs::write(temp_dir.path().join("b.txt"), "three\nfour\r\n").unwrap();
+
+        let options = ConcatOptions {
+            write_filenames: false,
+            write_tree: false,
+            ensure_trailing_newline: true,
+            line_endings: LineEndings::Lf,
+            ..test_options(temp_dir.path())
+        };
+        let mut output = Vec::new();
+        write_selected(&options, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "one\ntwo\nthree\nfour\n"
+        );
+
+        let options = ConcatOptions {
+            line_endings: LineEndings::Crlf,
+            ..options
+        };
+        let mut output = Vec::new();
+        write_selected(&options, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "one\r\ntwo\r\nthree\r\nfour\r\n"
+        );
+    }
+
+    #[test]
+    fn test_read_error() {
+        let temp_dir = create_test_directory();
+        let options = ConcatOptions {
+            patterns: vec!["*.txt".to_string(), "*.ts".to_string()],
+            write_tree: false,
+            ..test_options(temp_dir.path())
+        };
+        let mut files = select_files(&options, None).unwrap();
+        // Deleted after the walk, so reading it fails.
+        files.push(CollectedFile {
+            path: temp_dir.path().join("gone.txt"),
+            root: Some(0),
+            depth: 0,
+            len: 0,
+        });
+
+        let mut output = Vec::new();
+        write_files(&options, &mut output, &files, &options.directories).unwrap();
+        let output_content = String::from_utf8(output).unwrap();
+        assert!(output_content.contains("Content of file1"));
+        assert!(output_content.contains("Content of file2"));
+        assert!(output_content.contains("// [error reading: "));
+        assert!(output_content.contains("gone.txt"));
+
+        let options = ConcatOptions {
+            fail_fast: true,
+            ..options
+        };
+        assert!(write_files(&options, io::sink(), &files, &options.directories).is_err());
+    }
+
+    #[test]
+    fn test_prepend_and_append_files() {
+        let temp_dir = create_test_directory();
+        let templates = TempDir::new().unwrap();
+        let prepend = templates.path().join("prepend.txt");
+        let append = templates.path().join("append.txt");
+        fs::write(&prepend, "Review this code for {dir}:").unwrap();
+        fs::write(&append, "List any bugs.\n").unwrap();
+
+        let options = ConcatOptions {
+            patterns: vec!["file2.ts".to_string()],
+            write_filenames: false,
+            format: OutputFormat::Markdown,
+            filtered_tree: true,
+            prepend_file: Some(prepend),
+            append_file: Some(append),
+            ..test_options(temp_dir.path())
+        };
+        let mut output = Vec::new();
+        write_selected(&options, &mut output).unwrap();
+
+        let output_content = String::from_utf8(output).unwrap();
+        assert!(output_content.starts_with("Review this code for {dir}:\n```text\n"));
+        assert!(output_content.ends_with("```\n\nList any bugs.\n"));
+    }
+
+    #[test]
+    fn test_checksums() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("a.txt"), "hello\n").unwrap();
+
+        let options = ConcatOptions {
+            write_tree: false,
+            relative_paths: true,
+            checksums: true,
+            ..test_options(temp_dir.path())
+        };
+        let mut output = Vec::new();
+        write_selected(&options, &mut output).unwrap();
+
+        let output_content = String::from_utf8(output).unwrap();
+        assert_eq!(output_content, "// a.txt [5891b5b5]\nhello\n\n");
+    }
+
+    #[test]
+    fn test_truncate_file() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("big.txt"), "0123456789\nabcdef\n").unwrap();
+        fs::write(temp_dir.path().join("small.txt"), "tiny\n").unwrap();
+        let output_file = temp_dir.path().join("output.txt");
+
+        let options = ConcatOptions {
+            write_filenames: false,
+            write_tree: false,
+            truncate_file: Some(8),
+            ..test_options(temp_dir.path())
+        };
+        let output_content = concatenate_to_file(&options, &output_file);
+        assert!(output_content.contains("01234567\n// [truncated, 10 more bytes]\n"));
+        assert!(!output_content.contains("89"));
+        assert!(output_content.contains("tiny\n"));
+
+        fs::rename(
+            temp_dir.path().join("big.txt"),
+            temp_dir.path().join("big.rs"),
+        )
+        .unwrap();
+        let options = ConcatOptions {
+            strip_comments: true,
+            line_numbers: true,
+            ..options
+        };
+        let output_content = concatenate_to_file(&options, &output_file);
+        assert!(output_content.contains("1 | 01234567\n// [truncated, 10 more bytes]\n"));
+    }
+
+    #[test]
+    fn test_head_and_tail() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("long.txt"), "1\n2\n3\n4\n5\n").unwrap();
+
+        let options = ConcatOptions {
+            write_filenames: false,
+            write_tree: false,
+            head: Some(1),
+            tail: Some(1),
+            line_numbers: true,
+            ..test_options(temp_dir.path())
+        };
+        let mut output = Vec::new();
+        write_selected(&options, &mut output).unwrap();
+
+        let output_content = String::from_utf8(output).unwrap();
+        assert!(output_content.starts_with("1 | 1\n// [3 lines omitted]\n5 | 5\n"));
+    }
+
+    #[test]
+    fn test_max_file_size() {
+        let temp_dir = create_test_directory();
+        fs::write(temp_dir.path().join("large.ts"), "x".repeat(100)).unwrap();
+
+        let options = ConcatOptions {
+            patterns: vec!["**/*.ts".to_string()],
+            write_tree: false,
+            max_file_size: Some(16),
+            ..test_options(temp_dir.path())
+        };
+
+        let mut output = Vec::new();
+        write_selected(&options, &mut output).unwrap();
+
+        let output_content = String::from_utf8(output).unwrap();
+        assert!(output_content.contains("Content of file2"));
+        assert!(output_content.contains("// [skipped: 100 bytes exceeds limit]"));
+        assert!(!output_content.contains("xxxx"));
+    }
+
+    #[test]
+    fn test_traversal() {
+        let temp_dir = TempDir::new().unwrap();
+        for file in ["a/b/deep.txt", "a/mid.txt", "top.txt", "z/mid.txt"] {
+            let path = temp_dir.path().join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+
+        let order = |traversal| {
+            let options = ConcatOptions {
+                traversal,
+                ..test_options(temp_dir.path())
+            };
+            let file_filter = build_file_filter(&options).unwrap();
+            collect_files(&options, &file_filter, None, &mut Vec::new())
+                .unwrap()
+                .into_iter()
+                .map(|file| file.path.strip_prefix(temp_dir.path()).unwrap().to_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            order(Traversal::Dfs),
+            ["a/b/deep.txt", "a/mid.txt", "top.txt", "z/mid.txt"].map(PathBuf::from)
+        );
+        assert_eq!(
+            order(Traversal::Bfs),
+            ["top.txt", "a/mid.txt", "z/mid.txt", "a/b/deep.txt"].map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn test_max_files_per_dir() {
+        let temp_dir = TempDir::new().unwrap();
+        for file in [
+            "a.txt",
+            "b.txt",
+            "c.txt",
+            "sub/d.txt",
+            "sub/e.txt",
+            "sub/f.txt",
+        ] {
+            let path = temp_dir.path().join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+
+        let options = ConcatOptions {
+            max_files_per_dir: Some(2),
+            ..test_options(temp_dir.path())
+        };
+        let file_filter = build_file_filter(&options).unwrap();
+        let files: Vec<PathBuf> = collect_files(&options, &file_filter, None, &mut Vec::new())
+            .unwrap()
+            .into_iter()
+            .map(|file| file.path.strip_prefix(temp_dir.path()).unwrap().to_owned())
+            .collect();
+        assert_eq!(
+            files,
+            ["a.txt", "b.txt", "sub/d.txt", "sub/e.txt"].map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn test_order_file() {
+        let temp_dir = create_test_directory();
+
+        let options = ConcatOptions {
+            patterns: vec!["**/*.ts".to_string(), "!**/node_modules/**".to_string()],
+            order_file: vec![
+                PathBuf::from("./subdir/file3.ts"),
+                PathBuf::from("missing.ts"),
+            ],
+            write_filenames: false,
+            write_tree: false,
+            ..test_options(temp_dir.path())
+        };
+
+        let mut output = Vec::new();
+        write_selected(&options, &mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "Content of file3\nContent of file2\n"
+        );
+    }
+
+    #[test]
+    fn test_sort_path() {
+        let temp_dir = create_test_directory();
+
+        let options = ConcatOptions {
+            patterns: vec!["**/*.ts".to_string()],
+            write_tree: false,
+            sort: SortMode::Path,
+            ..test_options(temp_dir.path())
+        };
+
+        let mut output = Vec::new();
+        write_selected(&options, &mut output).unwrap();
+
+        let output_content = String::from_utf8(output).unwrap();
+        let file2 = output_content.find("Content of file2").unwrap();
+        let file3 = output_content.find("Content of file3").unwrap();
+        let file4 = output_content.find("Content of file4").unwrap();
+        assert!(file2 < file4 && file4 < file3);
+    }
+
+    #[test]
+    fn test_multiple_directories() {
+        let first_dir = create_test_directory();
+        let second_dir = TempDir::new().unwrap();
+        fs::write(second_dir.path().join("other.ts"), "Content of other").unwrap();
+        let output_file = first_dir.path().join("output.txt");
+
+        let options = ConcatOptions {
+            directories: vec![
+                first_dir.path().to_path_buf(),
+                second_dir.path().to_path_buf(),
+            ],
+            patterns: vec!["**/*.ts".to_string(), "**/*.txt".to_string()],
+            write_filenames: true,
+            write_tree: true,
+            ..test_options(first_dir.path())
+        };
+
+        let output_content = concatenate_to_file(&options, &output_file);
+
+        assert!(output_content.contains("Content of file2"));
+        assert!(output_content.contains("Content of other"));
+        assert!(output_content.contains("other.ts"));
+        assert_eq!(output_content.matches("output.txt").count(), 1);
+    }
+
+    #[test]
+    fn test_concactiignore() {
+        let temp_dir = create_test_directory();
+        fs::write(
+            temp_dir.path().join(IGNORE_FILE_NAME),
+            "# vendored code\n**/node_modules/**\n\n**/*.txt\n",
+        )
+        .unwrap();
+
+        let options = ConcatOptions {
+            patterns: vec!["!**/subdir/**".to_string()],
+            write_filenames: false,
+            write_tree: false,
+            ..test_options(temp_dir.path())
+        };
+
+        let mut output = Vec::new();
+        write_selected(&options, &mut output).unwrap();
+
+        let output_content = String::from_utf8(output).unwrap();
+        assert!(!output_content.contains("Content of file1"));
+        assert!(output_content.contains("Content of file2"));
+        assert!(!output_content.contains("Content of file3"));
+        assert!(!output_content.contains("Content of file4"));
+    }
+
+    #[test]
+    fn test_read_pattern_lines() {
+        let temp_dir = TempDir::new().unwrap();
+        let pattern_file = temp_dir.path().join("patterns");
+        fs::write(&pattern_file, "# comment\n\n  **/*.rs  \n!**/target/**\n").unwrap();
+
+        assert_eq!(
+            read_pattern_lines(&pattern_file).unwrap(),
+            ["**/*.rs", "!**/target/**"]
+        );
+    }
+
+    #[test]
+    fn test_summary() {
+        let temp_dir = create_test_directory();
+        fs::write(temp_dir.path().join("large.ts"), "x".repeat(100)).unwrap();
+
+        let options = ConcatOptions {
+            patterns: vec!["**/*.ts".to_string()],
+            write_filenames: false,
+            write_tree: false,
+            max_file_size: Some(16),
+            summary: true,
+            ..test_options(temp_dir.path())
+        };
+
+        let mut output = Vec::new();
+        let stats = write_selected(&options, &mut output).unwrap();
+
+        let output_content = String::from_utf8(output).unwrap();
+        assert!(output_content.ends_with("// 3 files, 48 bytes, 1 skipped\n"));
+        assert_eq!((stats.files, stats.bytes), (3, 48));
+        for included in &stats.included {
+            let start = included.offset as usize;
+            let entry = &output_content[start..start + included.length as usize];
+            assert!(entry.starts_with("Content of file"));
+        }
+        assert_eq!(
+            stats.skipped,
+            [SkippedFile {
+                path: temp_dir.path().join("large.ts"),
+                reason: "skipped: 100 bytes exceeds limit".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_hidden() {
+        let temp_dir = create_test_directory();
+        fs::write(temp_dir.path().join(".secret"), "Hidden secret").unwrap();
+        fs::create_dir(temp_dir.path().join(".git")).unwrap();
+        fs::write(temp_dir.path().join(".git").join("config"), "Git config").unwrap();
+
+        for hidden in [false, true] {
+            let options = ConcatOptions {
+                write_filenames: false,
+                write_tree: true,
+                hidden,
+                ..test_options(temp_dir.path())
+            };
+
+            let mut output = Vec::new();
+            write_selected(&options, &mut output).unwrap();
+
+            let output_content = String::from_utf8(output).unwrap();
+            assert!(output_content.contains("Content of file1"));
+            assert_eq!(output_content.contains("Hidden secret"), hidden);
+            assert_eq!(output_content.contains("Git config"), hidden);
+            // The tree leaves them out too.
+            assert_eq!(output_content.contains(".secret"), hidden);
+            assert_eq!(output_content.contains(".git"), hidden);
+        }
+    }
+
+    #[test]
+    fn test_auto_comment() {
+        let temp_dir = create_test_directory();
+        fs::write(temp_dir.path().join("script.py"), "print('hi')").unwrap();
+        fs::write(temp_dir.path().join("page.html"), "<p>hi</p>").unwrap();
+        fs::write(temp_dir.path().join("notes.unknown"), "notes").unwrap();
+
+        let options = ConcatOptions {
+            patterns: vec![
+                "**/file2.ts".to_string(),
+                "**/script.py".to_string(),
+                "**/page.html".to_string(),
+                "**/notes.unknown".to_string(),
+            ],
+            write_tree: false,
+            comment_style: ";;".to_string(),
+            auto_comment: true,
+            ..test_options(temp_dir.path())
+        };
+
+        let mut output = Vec::new();
+        write_selected(&options, &mut output).unwrap();
+
+        let output_content = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output_content.lines().collect();
+        assert!(lines
+            .iter()
+            .any(|line| line.starts_with("// ") && line.ends_with("file2.ts")));
+        assert!(lines
+            .iter()
+            .any(|line| line.starts_with("# ") && line.ends_with("script.py")));
+        assert!(lines
+            .iter()
+            .any(|line| line.starts_with("<!-- ") && line.ends_with("page.html -->")));
+        assert!(lines
+            .iter()
+            .any(|line| line.starts_with(";; ") && line.ends_with("notes.unknown")));
+    }
+
+    #[test]
+    fn test_output_in_tree_refused() {
+        let temp_dir = create_test_directory();
+        // Not written yet, as with output that is renamed into place.
+        let output_file = temp_dir.path().join("subdir").join("output.txt");
+        let mut concatenator = Concatenator::new(test_options(temp_dir.path()));
+        concatenator.exclude_output(&output_file).unwrap();
+
+        let error = concatenator.select_files().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(error.to_string().contains("--allow-output-in-tree"));
+
+        let elsewhere = TempDir::new().unwrap();
+        let mut concatenator = Concatenator::new(test_options(temp_dir.path()));
+        concatenator
+            .exclude_output(&elsewhere.path().join("output.txt"))
+            .unwrap();
+        assert!(concatenator.select_files().is_ok());
+    }
+
+    #[test]
+    fn test_output_guard_avoids_canonicalize() {
+        let temp_dir = TempDir::new().unwrap();
+        let subdir = temp_dir.path().join("subdir");
+        fs::create_dir(&subdir).unwrap();
+        for i in 0..1000 {
+            fs::write(subdir.join(format!("file{}.txt", i)), "contents").unwrap();
+        }
+        let output_file = temp_dir.path().join("output.txt");
+        fs::write(&output_file, "").unwrap();
+        let output_path = fs::canonicalize(&output_file).unwrap();
+
+        let options = test_options(temp_dir.path());
+        let guard = OutputGuard::new(&options, &output_path).unwrap().unwrap();
+
+        let candidates = fs::read_dir(&subdir)
+            .unwrap()
+            .chain(fs::read_dir(temp_dir.path()).unwrap())
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| guard.may_be_output(path))
+            .collect::<Vec<_>>();
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0], output_file);
+        assert!(guard.is_output(&output_file).unwrap());
+
+        let elsewhere = TempDir::new().unwrap();
+        let options = test_options(elsewhere.path());
+        assert!(OutputGuard::new(&options, &output_path).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_include_from() {
+        let temp_dir = create_test_directory();
+        let pattern_file = temp_dir.path().join("patterns.txt");
+        fs::write(
+            &pattern_file,
+            "# sources\n**/*.ts\n\n# vendored\n!**/node_modules/**\n",
+        )
+        .unwrap();
+
+        let options = ConcatOptions {
+            patterns: vec!["**/file1.txt".to_string()],
+            include_from: vec![pattern_file],
+            write_filenames: false,
+            write_tree: false,
+            ..test_options(temp_dir.path())
+        };
+
+        let mut output = Vec::new();
+        write_selected(&options, &mut output).unwrap();
+
+        let output_content = String::from_utf8(output).unwrap();
+        assert!(output_content.contains("Content of file1"));
+        assert!(output_content.contains("Content of file2"));
+        assert!(output_content.contains("Content of file3"));
+        assert!(!output_content.contains("Content of file4"));
+        assert!(!output_content.contains("# sources"));
+    }
+
+    #[test]
+    fn test_quiet_and_verbose() {
+        let options = ConcatOptions::parse_from(["concacti", "-d", ".", "-vv"]);
+        assert_eq!(options.verbose, 2);
+        assert!(!options.quiet);
+
+        assert!(ConcatOptions::try_parse_from(["concacti", "-d", ".", "-q"])
+            .is_ok_and(|options| options.quiet));
+        assert!(ConcatOptions::try_parse_from(["concacti", "-d", ".", "-q", "-v"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512").unwrap(), 512);
+        assert_eq!(parse_size("10k").unwrap(), 10 * 1024);
+        assert_eq!(parse_size("2M").unwrap(), 2 * 1024 * 1024);
+        assert_eq!(parse_size("1GB").unwrap(), 1 << 30);
+        assert!(parse_size("k").is_err());
+        assert!(parse_size("10x").is_err());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("2h").unwrap(), Duration::from_secs(7200));
+        assert_eq!(parse_duration("1w").unwrap(), Duration::from_secs(604800));
+        assert!(parse_duration("3").is_err());
+        assert!(parse_duration("d").is_err());
+        assert!(parse_duration("2y").is_err());
+    }
+
+    #[test]
+    fn test_modified_within_and_before() {
+        let temp_dir = create_test_directory();
+        let three_days_ago = SystemTime::now() - Duration::from_secs(3 * 24 * 60 * 60);
+        File::options()
+            .write(true)
+            .open(temp_dir.path().join("file1.txt"))
+            .unwrap()
+            .set_modified(three_days_ago)
+            .unwrap();
+        let names = |options: ConcatOptions| {
+            select_files(&options, None)
+                .unwrap()
+                .iter()
+                .map(|file| file.path.file_name().unwrap().to_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let recent = names(ConcatOptions {
+            patterns: vec!["*.*".to_string()],
+            modified_within: Some(Duration::from_secs(24 * 60 * 60)),
+            ..test_options(temp_dir.path())
+        });
+        assert!(!recent.is_empty());
+        assert!(!recent.contains(&"file1.txt".to_string()));
+
+        let old = names(ConcatOptions {
+            patterns: vec!["*.*".to_string()],
+            modified_before: Some(Duration::from_secs(24 * 60 * 60)),
+            ..test_options(temp_dir.path())
+        });
+        assert_eq!(old, ["file1.txt"]);
+    }
+
+    #[test]
+    fn test_parse_replacement() {
+        let replacement = parse_replacement(r#"key="[^"]*"=>key="REDACTED""#).unwrap();
+        assert_eq!(replacement.pattern.as_str(), r#"key="[^"]*""#);
+        assert_eq!(replacement.replacement, r#"key="REDACTED""#);
+        assert!(parse_replacement("no separator").is_err());
+        assert!(parse_replacement("(unclosed=>x").is_err());
+    }
+
+    #[test]
+    fn test_file_size_band() {
+        let temp_dir = create_test_directory();
+        fs::write(temp_dir.path().join("tiny.ts"), "x").unwrap();
+        fs::write(temp_dir.path().join("large.ts"), "x".repeat(2048)).unwrap();
+
+        let options = ConcatOptions {
+            patterns: vec!["*.ts".to_string()],
+            write_tree: false,
+            min_file_size: Some(8),
+            max_file_size: Some(1024),
+            ..test_options(temp_dir.path())
+        };
+        let mut output = Vec::new();
+        write_selected(&options, &mut output).unwrap();
+
+        let output_content = String::from_utf8(output).unwrap();
+        assert!(output_content.contains("Content of file2"));
+        assert!(!output_content.contains("tiny.ts"));
+        assert!(output_content.contains("[skipped: 2048 bytes exceeds limit]"));
+    }
+
+    #[test]
+    fn test_parse_escapes() {
+        assert_eq!(parse_escapes("----").unwrap(), "----");
+        assert_eq!(parse_escapes("\\n---\\n").unwrap(), "\n---\n");
+        assert_eq!(parse_escapes("a\\tb\\\\n").unwrap(), "a\tb\\n");
+        assert!(parse_escapes("\\x").is_err());
+    }
+
+    #[test]
+    fn test_separator() {
+        let temp_dir = create_test_directory();
+
+        let options = ConcatOptions {
+            patterns: vec!["**/*.ts".to_string(), "!**/node_modules/**".to_string()],
+            write_filenames: false,
+            write_tree: false,
+            separator: Some("-----".to_string()),
+            ..test_options(temp_dir.path())
+        };
+
+        let mut output = Vec::new();
+        write_selected(&options, &mut output).unwrap();
+
+        let output_content = String::from_utf8(output).unwrap();
+        assert_eq!(
+            output_content,
+            "Content of file2\n-----\nContent of file3\n"
+        );
+    }
+
+    #[test]
+    fn test_blank_lines() {
+        let temp_dir = create_test_directory();
+
+        let options = ConcatOptions {
+            patterns: vec!["**/*.ts".to_string(), "!**/node_modules/**".to_string()],
+            write_filenames: false,
+            write_tree: false,
+            separator: Some("-----".to_string()),
+            blank_lines: 2,
+            ..test_options(temp_dir.path())
+        };
+
+        let mut output = Vec::new();
+        write_selected(&options, &mut output).unwrap();
+
+        let output_content = String::from_utf8(output).unwrap();
+        assert_eq!(
+            output_content,
+            "Content of file2\n\n\n-----\nContent of file3\n"
+        );
+    }
+
+    #[test]
+    fn test_tree_only() {
+        let temp_dir = create_test_directory();
+        fs::write(temp_dir.path().join(".secret"), "Hidden secret").unwrap();
+        let options = ConcatOptions {
+            max_depth: 1,
+            tree_only: true,
+            write_tree: false,
+            ..test_options(temp_dir.path())
+        };
+
+        let mut output = Vec::new();
+        write_selected(&options, &mut output).unwrap();
+
+        let output_content = String::from_utf8(output).unwrap();
+        assert!(output_content.contains("file3.ts"));
+        assert!(!output_content.contains(".secret"));
+        assert!(!output_content.contains("Content of"));
+        assert!(!output_content.contains("// "));
+    }
+
+    #[test]
+    fn test_no_content() {
+        let temp_dir = create_test_directory();
+        let options = ConcatOptions {
+            patterns: vec!["**/*.ts".to_string(), "!**/node_modules/**".to_string()],
+            relative_paths: true,
+            no_content: true,
+            ..test_options(temp_dir.path())
+        };
+
+        // The files aren't read, so one deleted after selection still shows up.
+        let files = select_files(&options, None).unwrap();
+        fs::remove_file(temp_dir.path().join("file2.ts")).unwrap();
+        let mut output = Vec::new();
+        let stats = write_files(&options, &mut output, &files, &options.directories).unwrap();
+
+        let output_content = String::from_utf8(output).unwrap();
+        assert!(output_content.ends_with("// file2.ts\n// subdir/file3.ts\n"));
+        assert!(!output_content.contains("Content of"));
+        assert_eq!(stats.bytes, 32);
+    }
+
+    #[test]
+    fn test_filtered_tree() {
+        let temp_dir = create_test_directory();
+
+        let options = ConcatOptions {
+            patterns: vec!["**/*.ts".to_string(), "!**/node_modules/**".to_string()],
+            write_filenames: false,
+            write_tree: true,
+            filtered_tree: true,
+            ..test_options(temp_dir.path())
+        };
+
+        let mut output = Vec::new();
+        write_selected(&options, &mut output).unwrap();
+
+        let output_content = String::from_utf8(output).unwrap();
+        assert!(output_content.contains("subdir"));
+        assert!(output_content.contains("file2.ts"));
+        assert!(output_content.contains("file3.ts"));
+        assert!(!output_content.contains("node_modules"));
+        assert!(!output_content.contains("file1.txt"));
+        assert!(!output_content.contains("file4.ts"));
+    }
+
+    #[test]
+    fn test_relative_paths() {
+        let temp_dir = create_test_directory();
+
+        let options = ConcatOptions {
+            patterns: vec!["**/file3.ts".to_string()],
+            write_tree: false,
+            relative_paths: true,
+            ..test_options(temp_dir.path())
+        };
+
+        let mut output = Vec::new();
+        write_selected(&options, &mut output).unwrap();
+
+        let output_content = String::from_utf8(output).unwrap();
+        let expected = format!("// {}\n", Path::new("subdir").join("file3.ts").display());
+        assert!(output_content.starts_with(&expected));
+        assert!(!output_content.contains(&temp_dir.path().display().to_string()));
+    }
+
+    #[test]
+    fn test_relative_to() {
+        let temp_dir = create_test_directory();
+        let subdir = temp_dir.path().join("subdir");
+
+        let options = ConcatOptions {
+            patterns: vec!["*.txt".to_string(), "**/file3.ts".to_string()],
+            relative_to: Some(subdir.clone()),
+            ..test_options(temp_dir.path())
+        };
+        let mut output = Vec::new();
+        write_selected(&options, &mut output).unwrap();
+
+        let output_content = String::from_utf8(output).unwrap();
+        let file1 = temp_dir.path().join("file1.txt");
+        assert!(output_content.contains(&format!("// {}\n", file1.display())));
+        assert!(output_content.contains("// file3.ts\n"));
+        assert!(output_content.starts_with(&format!("{}\n", temp_dir.path().display())));
+
+        assert_eq!(relative_to(&subdir, &subdir), Path::new("."));
+    }
+
+    #[test]
+    fn test_files_from() {
+        let temp_dir = create_test_directory();
+        let list = format!(
+            "{}\n\n{}\n{}\n",
+            temp_dir
+                .path()
+                .join("node_modules")
+                .join("file4.ts")
+                .display(),
+            temp_dir.path().join("file1.txt").display(),
+            temp_dir.path().join("subdir/../file1.txt").display()
+        );
+        let list_file = temp_dir.path().join("list.txt");
+        fs::write(&list_file, list).unwrap();
+
+        let options = ConcatOptions {
+            directories: vec![],
+            files_from: Some(list_file),
+            patterns: vec!["!**/node_modules/**".to_string()],
+            write_tree: false,
+            comment_style: "#".to_string(),
+            ..test_options(temp_dir.path())
+        };
+
+        let mut output = Vec::new();
+        write_selected(&options, &mut output).unwrap();
+
+        let output_content = String::from_utf8(output).unwrap();
+        let file4 = output_content.find("Content of file4").unwrap();
+        let file1 = output_content.find("Content of file1").unwrap();
+        assert!(file4 < file1);
+        assert_eq!(output_content.matches("Content of file1").count(), 1);
+        assert!(output_content.contains("# "));
+        assert!(!output_content.contains("Content of file2"));
+    }
+
+    #[test]
+    fn test_input_list_format() {
+        let temp_dir = create_test_directory();
+        let file1 = temp_dir.path().join("file1.txt");
+        let file2 = temp_dir.path().join("file2.ts");
+        let paths = |files: Vec<CollectedFile>| -> Vec<PathBuf> {
+            files.into_iter().map(|file| file.path).collect()
+        };
+
+        let options = |input_list_format| ConcatOptions {
+            input_list_format,
+            ..test_options(temp_dir.path())
+        };
+
+        let json = serde_json::to_vec(&[&file1, &file2]).unwrap();
+        let files = read_file_list(json.as_slice(), &options(ListFormat::Json), None).unwrap();
+        assert_eq!(paths(files), [file1.clone(), file2.clone()]);
+
+        let null = format!("{}\0{}\0", file2.display(), file1.display());
+        let files = read_file_list(null.as_bytes(), &options(ListFormat::Null), None).unwrap();
+        assert_eq!(paths(files), [file2.clone(), file1.clone()]);
+
+        assert!(read_file_list(&b"not json"[..], &options(ListFormat::Json), None).is_err());
+
+        let missing = temp_dir.path().join("missing.txt");
+        let lines = format!("{}\n{}\n", missing.display(), file1.display());
+        let files = read_file_list(lines.as_bytes(), &options(ListFormat::Lines), None).unwrap();
+        assert_eq!(paths(files), [file1]);
+        let fail_fast = ConcatOptions {
+            fail_fast: true,
+            ..options(ListFormat::Lines)
+        };
+        assert!(read_file_list(lines.as_bytes(), &fail_fast, None).is_err());
+    }
+
+    #[test]
+    fn test_max_tokens() {
+        let temp_dir = create_test_directory();
+
+        // Each section is "Content of fileN\n", 17 bytes or 5 tokens.
+        let options = ConcatOptions {
+            patterns: vec!["**/*.ts".to_string()],
+            write_filenames: false,
+            write_tree: false,
+            max_tokens: Some(9),
+            ..test_options(temp_dir.path())
+        };
+
+        let mut output = Vec::new();
+        write_selected(&options, &mut output).unwrap();
+
+        let output_content = String::from_utf8(output).unwrap();
+        assert_eq!(output_content, "Content of file2\nContent of file4\n");
+    }
+
+    #[test]
+    fn test_template() {
+        let temp_dir = create_test_directory();
+        let options = ConcatOptions {
+            patterns: vec!["**/*.ts".to_string(), "!**/node_modules/**".to_string()],
+            write_tree: false,
+            separator: Some("----".to_string()),
+            template: Some(
+                parse_template("=== {index}. {relpath} ({size}, {lang}) ===\\n{content}").unwrap(),
+            ),
+            ..test_options(temp_dir.path())
+        };
+
+        let mut output = Vec::new();
+        write_selected(&options, &mut output).unwrap();
+        let expected_file3 = format!(
+            "=== 2. {} (16, ts) ===\nContent of file3\n",
+            Path::new("subdir").join("file3.ts").display()
+        );
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            format!(
+                "=== 1. file2.ts (16, ts) ===\nContent of file2\n{}",
+                expected_file3
+            )
+        );
+        assert!(parse_template("{relpath} {unknown}").is_err());
+    }
+
+    #[test]
+    fn test_max_total_size() {
+        let temp_dir = create_test_directory();
+        fs::write(temp_dir.path().join("image.ts"), b"Binary\0content").unwrap();
+
+        // The entries of file2 and file4 take 29 and 42 bytes. The skip note
+        // between them isn't counted, so both fit but file3's 36 bytes don't.
+        let options = ConcatOptions {
+            patterns: vec!["**/*.ts".to_string()],
+            skip_binary: true,
+            relative_paths: true,
+            write_tree: false,
+            max_total_size: Some(75),
+            ..test_options(temp_dir.path())
+        };
+
+        let mut output = Vec::new();
+        let stats = write_selected(&options, &mut output).unwrap();
+
+        let output_content = String::from_utf8(output).unwrap();
+        assert_eq!(
+            output_content,
+            "// file2.ts\nContent of file2\n\
+             // [skipped binary file] image.ts\n\
+             // node_modules/file4.ts\nContent of file4\n\
+             // [1 more files omitted by --max-total-size]\n"
+        );
+        assert_eq!(stats.skipped[1].reason, "over --max-total-size");
+    }
+
+    #[test]
+    fn test_strip_comments() {
+        let temp_dir = create_test_directory();
+        fs::write(
+            temp_dir.path().join("commented.ts"),
+            "// license\nconst url = \"http://x\"; // note\n",
+        )
+        .unwrap();
+
+        let options = ConcatOptions {
+            patterns: vec!["**/commented.ts".to_string()],
+            write_filenames: false,
+            write_tree: false,
+            strip_comments: true,
+            ..test_options(temp_dir.path())
+        };
+
+        let mut output = Vec::new();
+        write_selected(&options, &mut output).unwrap();
+
+        let output_content = String::from_utf8(output).unwrap();
+        assert_eq!(output_content, "const url = \"http://x\";\n\n");
+    }
+
+    #[test]
+    fn test_squeeze_blank() {
+        let temp_dir = create_test_directory();
+        fs::write(temp_dir.path().join("sparse.ts"), "a\n\n\n\nb\n").unwrap();
+
+        let options = ConcatOptions {
+            patterns: vec!["**/sparse.ts".to_string()],
+            write_filenames: false,
+            write_tree: false,
+            squeeze_blank: true,
+            ..test_options(temp_dir.path())
+        };
+
+        let mut output = Vec::new();
+        write_selected(&options, &mut output).unwrap();
+
+        assert_eq!(String::from_utf8(output).unwrap(), "a\n\nb\n\n");
+    }
+
+    #[test]
+    fn test_whitespace_cleanup() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("a.ts"), "if (x) {  \n\treturn;\t\n}\n").unwrap();
+        fs::write(temp_dir.path().join("b.py"), "if x:  \n\treturn\n").unwrap();
+
+        let options = ConcatOptions {
+            write_filenames: false,
+            write_tree: false,
+            trim_trailing: true,
+            tabs_to_spaces: Some(2),
+            ..test_options(temp_dir.path())
+        };
+
+        let mut output = Vec::new();
+        write_selected(&options, &mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "if (x) {\n  return;\n}\n\nif x:  \n\treturn\n\n"
+        );
+    }
+
+    #[test]
+    fn test_ext() {
+        let temp_dir = create_test_directory();
+        fs::write(temp_dir.path().join("component.tsx"), "Content of tsx").unwrap();
+        fs::write(temp_dir.path().join("types.d.ts"), "Content of types").unwrap();
+
+        let options = ConcatOptions {
+            patterns: vec!["!**/node_modules/**".to_string()],
+            ext: vec!["ts".to_string(), ".tsx".to_string()],
+            no_ext: vec![".d.ts".to_string()],
+            write_filenames: false,
+            write_tree: false,
+            ..test_options(temp_dir.path())
+        };
+
+        let mut output = Vec::new();
+        write_selected(&options, &mut output).unwrap();
+
+        let output_content = String::from_utf8(output).unwrap();
+        assert!(!output_content.contains("Content of file1"));
+        assert!(output_content.contains("Content of file2"));
+        assert!(output_content.contains("Content of file3"));
+        assert!(!output_content.contains("Content of file4"));
+        assert!(output_content.contains("Content of tsx"));
+        assert!(!output_content.contains("Content of types"));
+    }
+
+    #[test]
+    fn test_case_insensitive_patterns() {
+        let patterns = ["**/*.ts".to_string(), "!**/VENDOR/**".to_string()];
+        let sensitive = FileFilter::new(&patterns, false).unwrap();
+        let insensitive = FileFilter::new(&patterns, true).unwrap();
+
+        assert!(sensitive.should_process(Path::new("src/main.ts")));
+        assert!(!sensitive.should_process(Path::new("src/APP.TS")));
+        assert!(insensitive.should_process(Path::new("src/APP.TS")));
+        assert!(insensitive.should_process(Path::new("src/lib.Ts")));
+        assert!(sensitive.should_process(Path::new("vendor/lib.ts")));
+        assert!(!insensitive.should_process(Path::new("vendor/lib.ts")));
+    }
+
+    #[test]
+    fn test_order_by_pattern() {
+        let temp_dir = create_test_directory();
+        fs::write(temp_dir.path().join("subdir").join("notes.txt"), "").unwrap();
+        let options = ConcatOptions {
+            patterns: vec!["**/*.txt".to_string(), "**/subdir/*.ts".to_string()],
+            order_by_pattern: true,
+            ..test_options(temp_dir.path())
+        };
+
+        let files = select_files(&options, None).unwrap();
+        let names: Vec<_> = files
+            .iter()
+            .map(|file| file.path.file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(names, ["file1.txt", "notes.txt", "file3.ts"]);
+    }
+
+    #[test]
+    fn test_sort_by_size() {
+        let temp_dir = create_test_directory();
+        fs::write(temp_dir.path().join("big.ts"), "Content of a larger file").unwrap();
+        fs::write(temp_dir.path().join("subdir").join("tiny.ts"), "tiny").unwrap();
+        let names = |order| {
+            let options = ConcatOptions {
+                patterns: vec!["**/*.ts".to_string(), "!**/node_modules/**".to_string()],
+                sort_by_size: Some(order),
+                ..test_options(temp_dir.path())
+            };
+            select_files(&options, None)
+                .unwrap()
+                .iter()
+                .map(|file| file.path.file_name().unwrap().to_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            names(SizeOrder::Smallest),
+            ["tiny.ts", "file2.ts", "file3.ts", "big.ts"]
+        );
+        assert_eq!(
+            names(SizeOrder::Largest),
+            ["big.ts", "file2.ts", "file3.ts", "tiny.ts"]
+        );
+    }
+
+    #[test]
+    fn test_limit_after_reordering() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("a1.txt"), "a1 bytes").unwrap();
+        fs::write(temp_dir.path().join("m.md"), "m").unwrap();
+        fs::write(temp_dir.path().join("z.txt"), "z is large").unwrap();
+        let first = |options: ConcatOptions| {
+            let options = ConcatOptions {
+                limit: Some(1),
+                ..options
+            };
+            let files = select_files(&options, None).unwrap();
+            assert_eq!(files.len(), 1);
+            files[0]
+                .path
+                .file_name()
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+
+        let sorted = ConcatOptions {
+            sort_by_size: Some(SizeOrder::Largest),
+            ..test_options(temp_dir.path())
+        };
+        assert_eq!(first(sorted), "z.txt");
+        let by_pattern = ConcatOptions {
+            patterns: vec!["*.md".to_string(), "*.txt".to_string()],
+            order_by_pattern: true,
+            ..test_options(temp_dir.path())
+        };
+        assert_eq!(first(by_pattern), "m.md");
+        let pinned = ConcatOptions {
+            order_file: vec![PathBuf::from("z.txt")],
+            ..test_options(temp_dir.path())
+        };
+        assert_eq!(first(pinned), "z.txt");
+    }
+
+    #[test]
+    fn test_brace_patterns() {
+        assert_eq!(
+            split_patterns("**/*.{ts,tsx},!**/node_modules/**"),
+            ["**/*.{ts,tsx}", "!**/node_modules/**"]
+        );
+
+        let temp_dir = create_test_directory();
+        fs::write(temp_dir.path().join("view.tsx"), "Content of view").unwrap();
+        let options = ConcatOptions {
+            write_filenames: false,
+            write_tree: false,
+            ..ConcatOptions::parse_from([
+                OsStr::new("concacti"),
+                OsStr::new("-d"),
+                temp_dir.path().as_os_str(),
+                OsStr::new("-p"),
+                OsStr::new("**/*.{ts,tsx},!**/node_modules/**"),
+            ])
+        };
+
+        let mut output = Vec::new();
+        write_selected(&options, &mut output).unwrap();
+
+        let output_content = String::from_utf8(output).unwrap();
+        assert!(output_content.contains("Content of file2"));
+        assert!(output_content.contains("Content of view"));
+        assert!(!output_content.contains("Content of file1"));
+        assert!(!output_content.contains("Content of file4"));
+    }
+
+    #[test]
+    fn test_grep() {
+        let temp_dir = create_test_directory();
+        fs::write(temp_dir.path().join("todo.ts"), "// TODO: finish\n").unwrap();
+        let options = ConcatOptions {
+            patterns: vec!["**/*.ts".to_string(), "!**/node_modules/**".to_string()],
+            grep: Some(regex::bytes::Regex::new(r"TODO|file3").unwrap()),
+            ..test_options(temp_dir.path())
+        };
+
+        let names = |options: &ConcatOptions| {
+            let files = select_files(options, None).unwrap();
+            files
+                .iter()
+                .map(|file| file.path.file_name().unwrap().to_str().unwrap().to_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(&options), ["file3.ts", "todo.ts"]);
+
+        let options = ConcatOptions {
+            grep_invert: true,
+            ..options
+        };
+        assert_eq!(names(&options), ["file2.ts"]);
+
+        let options = ConcatOptions {
+            grep_invert: false,
+            limit: Some(1),
+            ..options
+        };
+        assert_eq!(names(&options), ["file3.ts"]);
+
+        fs::write(temp_dir.path().join("huge.ts"), "TODO ".repeat(100)).unwrap();
+        let options = ConcatOptions {
+            limit: None,
+            max_file_size: Some(100),
+            grep: Some(regex::bytes::Regex::new("nowhere").unwrap()),
+            ..options
+        };
+        assert_eq!(names(&options), ["huge.ts"]);
+    }
+
+    #[test]
+    fn test_exclusion_reasons() {
+        let file_filter = FileFilter::new(
+            &["**/*.ts".to_string(), "!**/node_modules/**".to_string()],
+            false,
+        )
+        .unwrap();
+
+        assert!(file_filter.exclusion(Path::new("src/main.ts")).is_none());
+        assert_eq!(
+            file_filter
+                .exclusion(Path::new("src/notes.txt"))
+                .unwrap()
+                .to_string(),
+            "matched no include pattern"
+        );
+        assert_eq!(
+            file_filter
+                .exclusion(Path::new("node_modules/lib.ts"))
+                .unwrap()
+                .to_string(),
+            "exclude pattern !**/node_modules/**"
+        );
+    }
+
+    #[test]
+    fn test_patterns_relative_to_root() {
+        let temp_dir = create_test_directory();
+        fs::create_dir(temp_dir.path().join("src")).unwrap();
+        fs::write(temp_dir.path().join("src").join("app.ts"), "").unwrap();
+        let names = |pattern: &str| {
+            let options = ConcatOptions {
+                patterns: vec![pattern.to_string(), "!**/node_modules/**".to_string()],
+                ..test_options(temp_dir.path())
+            };
+            select_files(&options, None)
+                .unwrap()
+                .iter()
+                .map(|file| file.path.file_name().unwrap().to_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(names("src/*.ts"), ["app.ts"]);
+        assert_eq!(names("subdir/*.ts"), ["file3.ts"]);
+        assert_eq!(names("**/*.ts"), ["file2.ts", "app.ts", "file3.ts"]);
+
+        let file_filter = FileFilter::new(&["src/*.ts".to_string()], false)
+            .unwrap()
+            .with_roots(&[temp_dir.path().to_path_buf()]);
+        assert!(file_filter.should_process(&temp_dir.path().join("src").join("main.ts")));
+        assert!(!file_filter.should_process(&temp_dir.path().join("lib").join("main.ts")));
+    }
+
+    #[test]
+    fn test_unmatched_patterns() {
+        let file_filter = FileFilter::new(
+            &[
+                "**/*.ts".to_string(),
+                "**/*.tss".to_string(),
+                "!**/node_modules/**".to_string(),
+            ],
+            false,
+        )
+        .unwrap();
+
+        let paths = [Path::new("src/main.ts"), Path::new("src/lib.ts")];
+        assert_eq!(
+            file_filter.unmatched_patterns(paths.into_iter()),
+            ["**/*.tss"]
+        );
+    }
+
+    #[test]
+    fn test_strict_globs() {
+        let temp_dir = create_test_directory();
+
+        let options = ConcatOptions {
+            patterns: vec!["**/*.ts".to_string(), "**/*.tss".to_string()],
+            write_tree: false,
+            strict_globs: true,
+            ..test_options(temp_dir.path())
+        };
+
+        let mut output = Vec::new();
+        let error = write_selected(&options, &mut output).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(error.to_string().contains("**/*.tss"));
+
+        let options = ConcatOptions {
+            strict_globs: false,
+            ..options
+        };
+        let mut output = Vec::new();
+        write_selected(&options, &mut output).unwrap();
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .contains("Content of file2"));
+
+        // A pattern whose files `--grep` then leaves out still matched them.
+        fs::write(temp_dir.path().join("notes.tss"), "unrelated").unwrap();
+        let options = ConcatOptions {
+            strict_globs: true,
+            grep: Some(regex::bytes::Regex::new("Content").unwrap()),
+            ..options
+        };
+        let mut output = Vec::new();
+        write_selected(&options, &mut output).unwrap();
+        assert!(!String::from_utf8(output).unwrap().contains("unrelated"));
+    }
+}
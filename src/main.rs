@@ -1,16 +1,25 @@
-use clap::{ArgAction, Parser};
-use globset::{Glob, GlobSet, GlobSetBuilder};
-use std::fs::{self, DirEntry, File};
-use std::io::{self, BufWriter, Write};
-use std::path::{Path, PathBuf};
-
-mod tree;
+use clap::{ArgAction, ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
+use concacti::{CollectedFile, ConcatOptions, Concatenator, Stats};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, IsTerminal, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::Instant;
+
+mod clipboard;
+mod config;
+mod split;
+mod transcode;
+mod watch;
 
 #[derive(Parser)]
 #[command(
-    author, 
-    version, 
-    about = "Concatenates files in a directory", 
+    author,
+    version,
+    about = "Concatenates files in a directory",
     long_about = None,
     after_help = "EXAMPLES:
     # Concatenate all .ts files, excluding those in node_modules
@@ -21,162 +30,551 @@ mod tree;
 
     # Use custom comment style and buffer size
     concacti -d ./docs -o output.md -p '**/*.md' --comment-style '<!--' --buffer-size 16384
-"
+
+    # Recreate the files from an earlier concatenation
+    concacti split output.txt --into ./restored
+",
+    subcommand_negates_reqs = true
 )]
 struct Cli {
-    /// Sets the input directory to use
-    #[arg(short, long, value_name = "DIR")]
-    directory: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
 
-    /// Sets the output file
+    #[command(flatten)]
+    options: ConcatOptions,
+
+    /// Sets the output file (omit or use - to write to stdout)
     #[arg(short, long, value_name = "FILE")]
-    output: PathBuf,
+    output: Option<PathBuf>,
+
+    /// Write one output file per top-level subdirectory into DIR instead, plus
+    /// `root` for the files directly under the input directory
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["output", "files_from", "clipboard"])]
+    output_dir: Option<PathBuf>,
 
-    /// File patterns to include or exclude (use ! for exclusion), comma-separated
-    #[arg(short, long, use_value_delimiter = true)]
-    patterns: Vec<String>,
+    /// Copy the matched files into DIR instead of concatenating them, each named
+    /// after its path with the components joined by `__`
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["output", "output_dir", "clipboard", "manifest"])]
+    flatten_into: Option<PathBuf>,
 
-    /// Maximum depth for recursive search
-    #[arg(long, default_value_t = usize::MAX)]
-    max_depth: usize,
+    /// Copy the output to the system clipboard; without --output nothing is written to stdout
+    #[arg(long, action = ArgAction::SetTrue)]
+    clipboard: bool,
 
-    /// Flag to write filenames as comments
-    #[arg(long, action = ArgAction::SetTrue, default_value_t = true)]
-    write_filenames: bool,
+    /// Compress the output with gzip (implied when the output file ends in .gz)
+    #[arg(long, action = ArgAction::SetTrue)]
+    gzip: bool,
 
-    /// Flag to write directory tree at the top of the output file
-    #[arg(long, action = ArgAction::SetTrue, default_value_t = true)]
-    write_tree: bool,
+    /// Also write FILE listing each included file with the byte offset and length of its entry in the output, as JSON lines
+    #[arg(long, value_name = "FILE", conflicts_with = "output_dir")]
+    manifest: Option<PathBuf>,
 
-    /// Comment style to use for filenames (default: //)
-    #[arg(long, default_value = "//")]
-    comment_style: String,
+    /// Character encoding to write the output in
+    #[arg(long, value_enum, default_value_t)]
+    output_encoding: transcode::OutputEncoding,
 
     /// Buffer size for writing (in bytes)
     #[arg(long, default_value_t = 8192)]
     buffer_size: usize,
+
+    /// List the files that would be included, with their sizes, on stderr without writing any output
+    #[arg(long, action = ArgAction::SetTrue)]
+    dry_run: bool,
+
+    /// Print the number of files and bytes per extension on stderr without writing any output
+    #[arg(long, action = ArgAction::SetTrue)]
+    extensions_summary: bool,
+
+    /// Print the paths of the files that would be included on stdout, one per line, without writing any output
+    #[arg(long, action = ArgAction::SetTrue, conflicts_with_all = ["dry_run", "extensions_summary"])]
+    list: bool,
+
+    /// Separate the paths printed by --list with NUL bytes instead of newlines, for `xargs -0`
+    #[arg(short = '0', long, action = ArgAction::SetTrue, requires = "list")]
+    null_separated: bool,
+
+    /// Keep running and regenerate the output whenever a matching file changes
+    #[arg(long, action = ArgAction::SetTrue)]
+    watch: bool,
+
+    /// Review the matched files in a checklist and untick any to leave out before writing
+    #[arg(long, action = ArgAction::SetTrue, conflicts_with = "watch")]
+    interactive: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Recreate the files of a plain concatenation written with filename comments
+    Split {
+        /// The concatenated file (use - for stdin)
+        input: PathBuf,
+
+        /// Directory to recreate the files under
+        #[arg(long, value_name = "DIR")]
+        into: PathBuf,
+
+        /// Comment style the filenames were written with
+        #[arg(long, default_value = "//")]
+        comment_style: String,
+    },
+}
+
+fn main() -> io::Result<()> {
+    let cli = cli_from_matches(&Cli::command().get_matches())?;
+    if let Some(Command::Split {
+        input,
+        into,
+        comment_style,
+    }) = &cli.command
+    {
+        let contents = if input == Path::new("-") {
+            let mut contents = Vec::new();
+            io::stdin().lock().read_to_end(&mut contents)?;
+            contents
+        } else {
+            fs::read(input)?
+        };
+        let count = split::split(&contents, into, comment_style)?;
+        status(
+            &cli,
+            format_args!("recreated {} files in {}", count, into.display()),
+        );
+        return Ok(());
+    }
+    let stats = concatenate_files(&cli)?;
+    if let Some(manifest) = &cli.manifest {
+        write_manifest(BufWriter::new(File::create(manifest)?), &stats)?;
+    }
+    if cli.options.summary {
+        eprintln!("{} in {:.2?}", stats, stats.elapsed);
+    }
+
+    if cli.watch {
+        watch::watch(&cli)?;
+    }
+    Ok(())
+}
+
+/// Builds the `Cli` from parsed arguments, applying the config file and then
+/// the `--no-*` flags, which win over both.
+fn cli_from_matches(matches: &ArgMatches) -> io::Result<Cli> {
+    let mut cli = Cli::from_arg_matches(matches).unwrap_or_else(|e| e.exit());
+    config::apply_config_file(&mut cli, matches)?;
+    cli.options.write_filenames &= !cli.options.no_write_filenames;
+    cli.options.write_tree &= !cli.options.no_write_tree;
+    // Manifest offsets count the UTF-8 bytes written before transcoding, and
+    // the encoding may also come from the config file, so clap can't check this.
+    if cli.manifest.is_some() && cli.output_encoding != transcode::OutputEncoding::Utf8 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "--manifest can only be used with --output-encoding utf8",
+        ));
+    }
+    Ok(cli)
 }
 
-struct FileFilter {
-    include: GlobSet,
-    exclude: GlobSet,
-    include_all: bool,
+/// Runs the concatenation `cli` asks for. With `--dry-run`, `--extensions-summary`
+/// or `--list` the stats count the files that would be included.
+fn concatenate_files(cli: &Cli) -> io::Result<Stats> {
+    let start = Instant::now();
+    let mut stats = write_selection(cli)?;
+    stats.elapsed = start.elapsed();
+    Ok(stats)
 }
 
-impl FileFilter {
-    fn new(patterns: &[String]) -> Result<Self, globset::Error> {
-        let mut include_builder = GlobSetBuilder::new();
-        let mut exclude_builder = GlobSetBuilder::new();
-        let mut include_all = true;
-
-        for pattern in patterns {
-            if let Some(pattern) = pattern.strip_prefix('!') {
-                exclude_builder.add(Glob::new(pattern)?);
-                include_all = false;
-            } else {
-                include_builder.add(Glob::new(pattern)?);
-                include_all = false;
-            }
+fn write_selection(cli: &Cli) -> io::Result<Stats> {
+    let mut options = cli.options.clone();
+    // Escape codes are only wanted when a person reads the output on a terminal.
+    options.highlight &= writes_to_terminal(cli);
+    let mut concatenator = Concatenator::new(options);
+    if let Some(flatten_into) = &cli.flatten_into {
+        return flatten_files(cli, &concatenator, flatten_into);
+    }
+    if cli.dry_run || cli.extensions_summary || cli.list {
+        // The output file may not exist yet, in which case there's nothing to exclude.
+        if let Some(output) = &cli.output {
+            concatenator.exclude_output(output).ok();
         }
+        let files = select_files(cli, &concatenator)?;
+        if cli.list {
+            let terminator = if cli.null_separated { b'\0' } else { b'\n' };
+            list_paths(io::stdout().lock(), &files, terminator)?;
+        } else if cli.extensions_summary {
+            list_extensions(io::stderr().lock(), &files)?;
+        } else {
+            list_files(&cli.options, io::stderr().lock(), &files)?;
+        }
+        return Ok(Stats {
+            files: files.len(),
+            bytes: files.iter().map(|file| file.len).sum(),
+            ..Stats::default()
+        });
+    }
+    if let Some(output_dir) = &cli.output_dir {
+        return write_output_dir(cli, &concatenator, output_dir);
+    }
 
-        if include_all {
-            include_builder.add(Glob::new("**/*")?);
+    match cli.output.as_deref().filter(|path| *path != Path::new("-")) {
+        Some(output) => {
+            concatenator.exclude_output(output)?;
+            let files = select_files(cli, &concatenator)?;
+            let gzip = cli.gzip || output.extension().is_some_and(|ext| ext == "gz");
+            let roots = &cli.options.directories;
+            write_atomically(output, |file| {
+                let writer = BufWriter::with_capacity(cli.buffer_size, file);
+                write_output(cli, &concatenator, writer, &files, roots, gzip)
+            })
+        }
+        None if cli.clipboard && cli.output.is_none() => {
+            let files = select_files(cli, &concatenator)?;
+            let roots = &cli.options.directories;
+            write_output(cli, &concatenator, io::sink(), &files, roots, false)
+        }
+        None => {
+            let files = select_files(cli, &concatenator)?;
+            let writer = BufWriter::with_capacity(cli.buffer_size, io::stdout().lock());
+            let roots = &cli.options.directories;
+            write_output(cli, &concatenator, writer, &files, roots, cli.gzip)
         }
+    }
+}
 
-        Ok(FileFilter {
-            include: include_builder.build()?,
-            exclude: exclude_builder.build()?,
-            include_all,
-        })
+/// Whether the output goes, uncompressed and as is, to a terminal.
+fn writes_to_terminal(cli: &Cli) -> bool {
+    cli.output
+        .as_deref()
+        .is_none_or(|output| output == Path::new("-"))
+        && cli.output_dir.is_none()
+        && !cli.clipboard
+        && !cli.gzip
+        && cli.output_encoding == transcode::OutputEncoding::Utf8
+        && io::stdout().is_terminal()
+}
+
+/// Selects the files to write, letting the user narrow them down with `--interactive`.
+fn select_files(cli: &Cli, concatenator: &Concatenator) -> io::Result<Vec<CollectedFile>> {
+    let files = concatenator.select_files()?;
+    if !cli.interactive || files.is_empty() {
+        return Ok(files);
     }
 
-    fn should_process(&self, path: &Path) -> bool {
-        (self.include_all || self.include.is_match(path)) && !self.exclude.is_match(path)
+    let labels: Vec<_> = files
+        .iter()
+        .map(|file| file.display_path(&cli.options).display().to_string())
+        .collect();
+    let chosen = dialoguer::MultiSelect::new()
+        .with_prompt("Files to include (space toggles, enter confirms)")
+        .items(&labels)
+        .defaults(&vec![true; files.len()])
+        .interact_opt()
+        .map_err(io::Error::other)?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::Interrupted, "selection cancelled"))?;
+
+    Ok(files
+        .into_iter()
+        .enumerate()
+        .filter(|(index, _)| chosen.contains(index))
+        .map(|(_, file)| file)
+        .collect())
+}
+
+/// Copies each selected file into `dir` under its flattened name. With
+/// `--dry-run`, only lists the copies on stderr.
+fn flatten_files(cli: &Cli, concatenator: &Concatenator, dir: &Path) -> io::Result<Stats> {
+    // Copies from an earlier run may sit inside an input directory.
+    let mut files = select_files(cli, concatenator)?;
+    if let Ok(dir) = fs::canonicalize(dir) {
+        files.retain(|file| {
+            fs::canonicalize(&file.path).map_or(true, |path| !path.starts_with(&dir))
+        });
+    }
+    if !cli.dry_run {
+        fs::create_dir_all(dir)?;
+    }
+
+    let mut stats = Stats::default();
+    let mut used = HashSet::new();
+    for file in &files {
+        let relative = file
+            .root
+            .and_then(|root| file.path.strip_prefix(&cli.options.directories[root]).ok())
+            .unwrap_or(&file.path);
+        let name = unique_name(flat_name(relative), &mut used);
+        let target = dir.join(&name);
+        if cli.dry_run {
+            eprintln!("{} -> {}", file.path.display(), target.display());
+            stats.bytes += file.len;
+        } else {
+            stats.bytes += fs::copy(&file.path, &target)?;
+        }
+        stats.files += 1;
+    }
+    if !cli.dry_run {
+        status(
+            cli,
+            format_args!("copied {} files to {}", stats.files, dir.display()),
+        );
     }
+    Ok(stats)
 }
 
-fn main() -> io::Result<()> {
-    let cli = Cli::parse();
-    concatenate_files(&cli)
+/// Joins the named components of `path` with `__`, so `src/app/main.rs`
+/// becomes `src__app__main.rs`.
+fn flat_name(path: &Path) -> String {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("__")
 }
 
-fn concatenate_files(cli: &Cli) -> io::Result<()> {
-    let file = File::create(&cli.output)?;
-    let mut writer = BufWriter::with_capacity(cli.buffer_size, file);
-    let directory = &cli.directory;
-    let output_path = fs::canonicalize(&cli.output)?;
-
-    let file_filter = FileFilter::new(&cli.patterns)
-        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
-
-    if cli.write_tree {
-        writeln!(writer, "{}", tree::tree(directory)?.to_string())?;
-    }
-
-    visit_dirs(
-        directory,
-        cli,
-        &file_filter,
-        &mut |entry| {
-            let path = entry.path();
-            if !path.is_file() {
-                return Ok(());
-            }
-            let canonical_path = fs::canonicalize(&path)?;
-            if canonical_path == output_path {
-                return Ok(());
-            }
-
-            if file_filter.should_process(&path) {
-                if cli.write_filenames {
-                    writeln!(writer, "{} {}", cli.comment_style, path.display())?;
-                }
-                let contents = fs::read(&path)?;
-                writer.write_all(&contents)?;
-                writeln!(writer)?;
-            }
-            Ok(())
-        },
-        0,
-    )?;
-
-    writer.flush()?;
-    Ok(())
+/// Returns `name`, or if it was used already, `name` with `-2`, `-3` and so
+/// on added before the extension.
+fn unique_name(name: String, used: &mut HashSet<String>) -> String {
+    if used.insert(name.clone()) {
+        return name;
+    }
+    let path = Path::new(&name);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    let mut n = 2;
+    loop {
+        let candidate = format!("{}-{}{}", stem, n, extension);
+        if used.insert(candidate.clone()) {
+            return candidate;
+        }
+        n += 1;
+    }
 }
 
-fn visit_dirs<F>(
-    dir: &Path,
+/// Writes one output file per top-level subdirectory of the input directories
+/// into `output_dir`, named after the subdirectory, with the files directly
+/// under an input directory going to `root`.
+fn write_output_dir(
     cli: &Cli,
-    file_filter: &FileFilter,
-    cb: &mut F,
-    depth: usize,
-) -> io::Result<()>
-where
-    F: FnMut(&DirEntry) -> io::Result<()>,
-{
-    if depth > cli.max_depth {
-        return Ok(());
+    concatenator: &Concatenator,
+    output_dir: &Path,
+) -> io::Result<Stats> {
+    fs::create_dir_all(output_dir)?;
+    let output_dir = fs::canonicalize(output_dir)?;
+
+    // Output files from an earlier run may sit inside an input directory.
+    let mut files = select_files(cli, concatenator)?;
+    files.retain(|file| {
+        fs::canonicalize(&file.path).map_or(true, |path| !path.starts_with(&output_dir))
+    });
+
+    // Group name -> (directories to draw trees of, files), in name order.
+    let mut groups: BTreeMap<String, (Vec<PathBuf>, Vec<CollectedFile>)> = BTreeMap::new();
+    for file in files {
+        let root = file.root.map(|root| &cli.options.directories[root]);
+        let top_level = root
+            .and_then(|root| file.path.strip_prefix(root).ok())
+            .filter(|relative| relative.components().count() > 1)
+            .and_then(|relative| relative.iter().next());
+        let (name, tree_root) = match (root, top_level) {
+            (Some(root), Some(top_level)) => (
+                top_level.to_string_lossy().into_owned(),
+                Some(root.join(top_level)),
+            ),
+            (root, _) => ("root".to_string(), root.cloned()),
+        };
+
+        let (tree_roots, group) = groups.entry(name).or_default();
+        if let Some(tree_root) = tree_root.filter(|tree_root| !tree_roots.contains(tree_root)) {
+            tree_roots.push(tree_root);
+        }
+        group.push(file);
     }
 
-    if dir.is_dir() {
-        for entry in fs::read_dir(dir)? {
-            let entry = entry?;
-            let path = entry.path();
-            if path.is_dir() {
-                visit_dirs(&path, cli, file_filter, cb, depth + 1)?;
-            } else {
-                cb(&entry)?;
-            }
+    let mut stats = Stats::default();
+    for (name, (tree_roots, files)) in groups {
+        let mut file_name = format!("{}.{}", name, cli.options.format.extension());
+        if cli.gzip {
+            file_name.push_str(".gz");
         }
+        let group_stats = write_atomically(&output_dir.join(file_name), |file| {
+            let writer = BufWriter::with_capacity(cli.buffer_size, file);
+            write_output(cli, concatenator, writer, &files, &tree_roots, cli.gzip)
+        })?;
+        stats.files += group_stats.files;
+        stats.skipped.extend(group_stats.skipped);
+        stats.bytes += group_stats.bytes;
     }
+    Ok(stats)
+}
 
-    Ok(())
+/// Runs `write_files`, compressing everything it writes when `gzip` is set
+/// and copying the uncompressed UTF-8 text to the clipboard with `--clipboard`.
+fn write_output<W: Write>(
+    cli: &Cli,
+    concatenator: &Concatenator,
+    writer: W,
+    files: &[CollectedFile],
+    tree_roots: &[PathBuf],
+    gzip: bool,
+) -> io::Result<Stats> {
+    let mut copied = Vec::new();
+    let copy = cli.clipboard.then_some(&mut copied);
+
+    let write_transcoded = |writer: &mut dyn Write, copy| {
+        let mut transcoder = transcode::Transcoder::new(writer, cli.output_encoding);
+        let stats = concatenator.write_files(
+            clipboard::Tee::new(&mut transcoder, copy),
+            files,
+            tree_roots,
+        )?;
+        let unrepresentable = transcoder.finish()?;
+        if unrepresentable > 0 {
+            status(
+                cli,
+                format_args!(
+                    "warning: {} characters have no Latin-1 form and were written as ?",
+                    unrepresentable
+                ),
+            );
+        }
+        Ok::<_, io::Error>(stats)
+    };
+    let stats = if gzip {
+        let mut encoder = GzEncoder::new(writer, Compression::default());
+        let stats = write_transcoded(&mut encoder, copy)?;
+        // Dropping the encoder would finish the stream too, but swallow any error.
+        encoder.finish()?.flush()?;
+        stats
+    } else {
+        let mut writer = writer;
+        write_transcoded(&mut writer, copy)?
+    };
+
+    if cli.clipboard {
+        clipboard::copy(&copied)?;
+        status(
+            cli,
+            format_args!("copied {} bytes to the clipboard", copied.len()),
+        );
+    }
+    Ok(stats)
+}
+
+/// Writes to a temporary file next to `output` and renames it over `output`
+/// once `write` succeeds, so nothing reading the output sees it half-written.
+/// On error the temporary file is removed and `output` is left untouched.
+fn write_atomically(
+    output: &Path,
+    write: impl FnOnce(File) -> io::Result<Stats>,
+) -> io::Result<Stats> {
+    let temp = temp_path(output);
+    let result = File::create(&temp)
+        .and_then(write)
+        .and_then(|stats| fs::rename(&temp, output).map(|()| stats));
+    if result.is_err() {
+        fs::remove_file(&temp).ok();
+    }
+    result
+}
+
+/// The hidden file `write_atomically` writes `output` to first.
+fn temp_path(output: &Path) -> PathBuf {
+    let name = output.file_name().unwrap_or_default().to_string_lossy();
+    output.with_file_name(format!(".{}.{}.tmp", name, std::process::id()))
+}
+
+/// Writes one JSON object per included file, with its path and where its
+/// entry starts and how long it is in the output.
+fn write_manifest<W: Write>(mut writer: W, stats: &Stats) -> io::Result<()> {
+    for included in &stats.included {
+        serde_json::to_writer(&mut writer, included)?;
+        writeln!(writer)?;
+    }
+    writer.flush()
+}
+
+/// Writes a table of how many files, and how many bytes, there are per
+/// extension, most common first.
+fn list_extensions<W: Write>(mut writer: W, files: &[CollectedFile]) -> io::Result<()> {
+    let mut extensions: BTreeMap<String, (usize, u64)> = BTreeMap::new();
+    for file in files {
+        let extension = match file.path.extension() {
+            Some(extension) => extension.to_string_lossy().into_owned(),
+            None => "(none)".to_string(),
+        };
+        let (count, bytes) = extensions.entry(extension).or_default();
+        *count += 1;
+        *bytes += file.len;
+    }
+
+    let mut rows: Vec<_> = extensions
+        .into_iter()
+        .map(|(extension, (count, bytes))| (extension, count, concacti::human_size(bytes)))
+        .collect();
+    // Stable, so equally common extensions stay in name order.
+    rows.sort_by_key(|row| std::cmp::Reverse(row.1));
+
+    let extension_width = rows.iter().map(|row| row.0.len()).max().unwrap_or(0);
+    let count_width = rows
+        .iter()
+        .map(|row| row.1.to_string().len())
+        .max()
+        .unwrap_or(0);
+    let size_width = rows.iter().map(|row| row.2.len()).max().unwrap_or(0);
+    for (extension, count, size) in rows {
+        let noun = if count == 1 { "file" } else { "files" };
+        writeln!(
+            writer,
+            "{:<extension_width$}  {:>count_width$} {:<5}  {:>size_width$}",
+            extension, count, noun, size
+        )?;
+    }
+
+    writer.flush()
+}
+
+/// Writes one line per file that would be concatenated, with its size.
+fn list_files<W: Write>(
+    options: &ConcatOptions,
+    mut writer: W,
+    files: &[CollectedFile],
+) -> io::Result<()> {
+    for file in files {
+        writeln!(
+            writer,
+            "{} ({} bytes)",
+            file.display_path(options).display(),
+            file.len
+        )?;
+    }
+
+    writer.flush()
+}
+
+/// Writes the path of each file followed by `terminator`, leaving them
+/// unescaped for other tools.
+fn list_paths<W: Write>(mut writer: W, files: &[CollectedFile], terminator: u8) -> io::Result<()> {
+    for file in files {
+        writer.write_all(file.path.as_os_str().as_encoded_bytes())?;
+        writer.write_all(&[terminator])?;
+    }
+
+    writer.flush()
+}
+
+/// Prints a status message on stderr unless `--quiet` is set.
+fn status(cli: &Cli, message: impl fmt::Display) {
+    if !cli.options.quiet {
+        eprintln!("{}", message);
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::fs::File;
+    use std::ffi::OsStr;
     use std::io::Read;
     use tempfile::TempDir;
 
@@ -198,50 +596,70 @@ mod tests {
         dir
     }
 
+    fn test_cli(directory: &Path, output: &Path) -> Cli {
+        Cli::parse_from([
+            OsStr::new("concacti"),
+            OsStr::new("-d"),
+            directory.as_os_str(),
+            OsStr::new("-o"),
+            output.as_os_str(),
+            OsStr::new("--allow-output-in-tree"),
+        ])
+    }
+
     #[test]
-    fn test_wildcard_include() {
+    fn test_no_write_tree_and_filenames() {
         let temp_dir = create_test_directory();
         let output_file = temp_dir.path().join("output.txt");
-
-        let cli = Cli {
-            directory: temp_dir.path().to_path_buf(),
-            output: output_file.clone(),
-            patterns: vec!["**/*.ts".to_string()],
-            max_depth: usize::MAX,
-            write_filenames: false,
-            write_tree: false,
-            comment_style: "//".to_string(),
-            buffer_size: 8192,
+        let args = |extra: &[&str]| {
+            let mut args = vec![
+                OsStr::new("concacti"),
+                OsStr::new("-d"),
+                temp_dir.path().as_os_str(),
+                OsStr::new("-o"),
+                output_file.as_os_str(),
+            ];
+            args.extend(extra.iter().map(OsStr::new));
+            cli_from_matches(&Cli::command().get_matches_from(args)).unwrap()
         };
 
-        concatenate_files(&cli).unwrap();
+        let cli = args(&[]);
+        assert!(cli.options.write_tree && cli.options.write_filenames);
 
-        let mut output_content = String::new();
-        File::open(output_file)
-            .unwrap()
-            .read_to_string(&mut output_content)
-            .unwrap();
+        let cli = args(&[
+            "--no-write-tree",
+            "--no-write-filenames",
+            "--allow-output-in-tree",
+        ]);
+        assert!(!cli.options.write_tree && !cli.options.write_filenames);
+        concatenate_files(&cli).unwrap();
+        let output_content = fs::read_to_string(&output_file).unwrap();
+        assert!(!output_content.contains("subdir"));
+        assert!(!output_content.contains("file1.txt"));
+        assert!(output_content.contains("Content of file1"));
 
-        assert!(!output_content.contains("Content of file1"));
-        assert!(output_content.contains("Content of file2"));
-        assert!(output_content.contains("Content of file3"));
-        assert!(output_content.contains("Content of file4"));
+        // The last of a flag and its negation wins.
+        let cli = args(&["--no-write-tree", "--write-tree"]);
+        assert!(cli.options.write_tree);
     }
 
     #[test]
-    fn test_wildcard_exclude() {
+    fn test_buffer_size() {
         let temp_dir = create_test_directory();
         let output_file = temp_dir.path().join("output.txt");
 
+        let cli = test_cli(temp_dir.path(), &output_file);
         let cli = Cli {
-            directory: temp_dir.path().to_path_buf(),
-            output: output_file.clone(),
-            patterns: vec!["**/*.ts".to_string(), "!**/node_modules/**".to_string()],
-            max_depth: usize::MAX,
-            write_filenames: false,
-            write_tree: false,
-            comment_style: "//".to_string(),
-            buffer_size: 8192,
+            options: ConcatOptions {
+                patterns: vec!["**/*.ts".to_string()],
+                max_depth: usize::MAX,
+                write_filenames: false,
+                write_tree: false,
+                comment_style: "//".to_string(),
+                ..cli.options
+            },
+            buffer_size: 1, // Minimum buffer size to test buffering
+            ..cli
         };
 
         concatenate_files(&cli).unwrap();
@@ -252,219 +670,356 @@ mod tests {
             .read_to_string(&mut output_content)
             .unwrap();
 
-        assert!(!output_content.contains("Content of file1"));
         assert!(output_content.contains("Content of file2"));
         assert!(output_content.contains("Content of file3"));
-        assert!(!output_content.contains("Content of file4"));
+        assert!(output_content.contains("Content of file4"));
     }
 
     #[test]
-    fn test_multiple_patterns() {
+    fn test_gzip_output() {
         let temp_dir = create_test_directory();
-        let output_file = temp_dir.path().join("output.txt");
+        let plain_file = temp_dir.path().join("output.txt");
+        let gzip_file = temp_dir.path().join("output.txt.gz");
 
+        // Leave the tree out, since it would list the first output in the second.
+        let cli = test_cli(temp_dir.path(), &plain_file);
         let cli = Cli {
-            directory: temp_dir.path().to_path_buf(),
-            output: output_file.clone(),
-            patterns: vec![
-                "**/*.ts".to_string(),
-                "**/*.txt".to_string(),
-                "!**/node_modules/**".to_string(),
-            ],
-            max_depth: usize::MAX,
-            write_filenames: false,
-            write_tree: false,
-            comment_style: "//".to_string(),
-            buffer_size: 8192,
+            options: ConcatOptions {
+                patterns: vec!["**/*.ts".to_string()],
+                write_tree: false,
+                ..cli.options
+            },
+            ..cli
+        };
+        concatenate_files(&cli).unwrap();
+        let cli = Cli {
+            output: Some(gzip_file.clone()),
+            ..cli
         };
-
         concatenate_files(&cli).unwrap();
 
-        let mut output_content = String::new();
-        File::open(output_file)
-            .unwrap()
-            .read_to_string(&mut output_content)
+        let mut decompressed = String::new();
+        flate2::read::GzDecoder::new(File::open(gzip_file).unwrap())
+            .read_to_string(&mut decompressed)
             .unwrap();
-
-        assert!(output_content.contains("Content of file1"));
-        assert!(output_content.contains("Content of file2"));
-        assert!(output_content.contains("Content of file3"));
-        assert!(!output_content.contains("Content of file4"));
+        assert_eq!(decompressed, fs::read_to_string(plain_file).unwrap());
+        assert!(decompressed.contains("Content of file2"));
     }
 
     #[test]
-    fn test_no_patterns() {
+    fn test_output_dir() {
         let temp_dir = create_test_directory();
-        let output_file = temp_dir.path().join("output.txt");
+        let output_dir = temp_dir.path().join("out");
 
         let cli = Cli {
-            directory: temp_dir.path().to_path_buf(),
-            output: output_file.clone(),
-            patterns: vec![],
-            max_depth: usize::MAX,
-            write_filenames: false,
-            write_tree: false,
-            comment_style: "//".to_string(),
-            buffer_size: 8192,
+            output: None,
+            output_dir: Some(output_dir.clone()),
+            ..test_cli(temp_dir.path(), Path::new("-"))
         };
-
+        // The second run must not pick up the first run's output files.
+        concatenate_files(&cli).unwrap();
         concatenate_files(&cli).unwrap();
 
-        let mut output_content = String::new();
-        File::open(output_file)
+        let mut names: Vec<_> = fs::read_dir(&output_dir)
             .unwrap()
-            .read_to_string(&mut output_content)
-            .unwrap();
-
-        assert!(output_content.contains("Content of file1"));
-        assert!(output_content.contains("Content of file2"));
-        assert!(output_content.contains("Content of file3"));
-        assert!(output_content.contains("Content of file4"));
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["node_modules.txt", "root.txt", "subdir.txt"]);
+
+        let root = fs::read_to_string(output_dir.join("root.txt")).unwrap();
+        assert!(root.contains("Content of file1"));
+        assert!(root.contains("Content of file2"));
+        assert!(!root.contains("Content of file3"));
+
+        let subdir = fs::read_to_string(output_dir.join("subdir.txt")).unwrap();
+        assert!(subdir.contains("file3.ts"));
+        assert!(subdir.contains("Content of file3"));
+        assert!(!subdir.contains("file1.txt"));
     }
 
     #[test]
-    fn test_max_depth() {
+    fn test_dry_run() {
         let temp_dir = create_test_directory();
         let output_file = temp_dir.path().join("output.txt");
 
+        let cli = test_cli(temp_dir.path(), &output_file);
         let cli = Cli {
-            directory: temp_dir.path().to_path_buf(),
-            output: output_file.clone(),
-            patterns: vec!["**/*.ts".to_string()],
-            max_depth: 0,
-            write_filenames: false,
-            write_tree: false,
-            comment_style: "//".to_string(),
-            buffer_size: 8192,
+            options: ConcatOptions {
+                patterns: vec!["**/*.ts".to_string(), "!**/node_modules/**".to_string()],
+                ..cli.options
+            },
+            dry_run: true,
+            ..cli
         };
 
         concatenate_files(&cli).unwrap();
+        assert!(!output_file.exists());
 
-        let mut output_content = String::new();
-        File::open(output_file)
-            .unwrap()
-            .read_to_string(&mut output_content)
+        let mut listing = Vec::new();
+        let files = Concatenator::new(cli.options.clone())
+            .select_files()
             .unwrap();
+        list_files(&cli.options, &mut listing, &files).unwrap();
 
-        assert!(output_content.contains("Content of file2"));
-        assert!(!output_content.contains("Content of file3"));
-        assert!(!output_content.contains("Content of file4"));
+        let listing = String::from_utf8(listing).unwrap();
+        assert_eq!(listing.lines().count(), 2);
+        assert!(listing.contains("file2.ts (16 bytes)"));
+        assert!(listing.contains("file3.ts (16 bytes)"));
+        assert!(!listing.contains("Content of"));
     }
 
     #[test]
-    fn test_comment_style() {
-        let temp_dir = create_test_directory();
+    fn test_output_encoding() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("café.txt"), "Grüße").unwrap();
         let output_file = temp_dir.path().join("output.txt");
 
+        let cli = test_cli(temp_dir.path(), &output_file);
         let cli = Cli {
-            directory: temp_dir.path().to_path_buf(),
-            output: output_file.clone(),
-            patterns: vec!["**/*.ts".to_string()],
-            max_depth: usize::MAX,
-            write_filenames: true,
-            write_tree: false,
-            comment_style: "#".to_string(),
-            buffer_size: 8192,
+            output_encoding: transcode::OutputEncoding::Utf16le,
+            ..cli
         };
-
         concatenate_files(&cli).unwrap();
 
-        let mut output_content = String::new();
-        File::open(output_file)
-            .unwrap()
-            .read_to_string(&mut output_content)
-            .unwrap();
-
-        assert!(output_content.contains("# "));
-        assert!(!output_content.contains("// "));
+        let bytes = fs::read(&output_file).unwrap();
+        assert_eq!(bytes[..2], [0xFF, 0xFE]);
+        let units: Vec<u16> = bytes[2..]
+            .chunks(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        let decoded = String::from_utf16(&units).unwrap();
+        assert!(decoded.contains("café.txt"));
+        assert!(decoded.contains("Grüße"));
     }
 
     #[test]
-    fn test_write_filenames() {
+    fn test_manifest() {
         let temp_dir = create_test_directory();
         let output_file = temp_dir.path().join("output.txt");
+        let cli = test_cli(temp_dir.path(), &output_file);
+        let stats = concatenate_files(&cli).unwrap();
+
+        let mut manifest = Vec::new();
+        write_manifest(&mut manifest, &stats).unwrap();
+        let output = fs::read(&output_file).unwrap();
+        let lines: Vec<serde_json::Value> = manifest
+            .split(|&b| b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 4);
+        for line in lines {
+            let start = line["offset"].as_u64().unwrap() as usize;
+            let end = start + line["length"].as_u64().unwrap() as usize;
+            let path = line["path"].as_str().unwrap();
+            let entry = String::from_utf8_lossy(&output[start..end]);
+            assert!(entry.starts_with(&format!("// {}\n", path)), "{:?}", entry);
+        }
+    }
 
-        let cli = Cli {
-            directory: temp_dir.path().to_path_buf(),
-            output: output_file.clone(),
-            patterns: vec!["**/*.ts".to_string()],
-            max_depth: usize::MAX,
-            write_filenames: true,
-            write_tree: false,
-            comment_style: "//".to_string(),
-            buffer_size: 8192,
+    #[test]
+    fn test_manifest_needs_utf8() {
+        let temp_dir = create_test_directory();
+        let manifest = temp_dir.path().join("manifest.jsonl");
+        let args = |encoding: &str| {
+            Cli::command().get_matches_from([
+                OsStr::new("concacti"),
+                OsStr::new("-d"),
+                temp_dir.path().as_os_str(),
+                OsStr::new("--manifest"),
+                manifest.as_os_str(),
+                OsStr::new("--output-encoding"),
+                OsStr::new(encoding),
+            ])
         };
 
-        concatenate_files(&cli).unwrap();
-
-        let mut output_content = String::new();
-        File::open(output_file)
-            .unwrap()
-            .read_to_string(&mut output_content)
-            .unwrap();
-
-        assert!(output_content.contains("// "));
-        assert!(output_content.contains("file2.ts"));
-        assert!(output_content.contains("file3.ts"));
+        assert!(cli_from_matches(&args("utf8")).is_ok());
+        let error = cli_from_matches(&args("latin1")).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
     }
 
     #[test]
-    fn test_write_tree() {
-        let temp_dir = create_test_directory();
+    fn test_write_atomically() {
+        let temp_dir = TempDir::new().unwrap();
         let output_file = temp_dir.path().join("output.txt");
+        fs::write(&output_file, "previous output").unwrap();
+
+        let result = write_atomically(&output_file, |mut file| {
+            file.write_all(b"partial")?;
+            Err(io::Error::other("interrupted"))
+        });
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&output_file).unwrap(), "previous output");
+        assert!(!temp_path(&output_file).exists());
+
+        write_atomically(&output_file, |mut file| {
+            file.write_all(b"new output")?;
+            Ok(Stats::default())
+        })
+        .unwrap();
+        assert_eq!(fs::read_to_string(&output_file).unwrap(), "new output");
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+    }
 
-        let cli = Cli {
-            directory: temp_dir.path().to_path_buf(),
-            output: output_file.clone(),
-            patterns: vec!["**/*.ts".to_string()],
-            max_depth: usize::MAX,
-            write_filenames: false,
-            write_tree: true,
-            comment_style: "//".to_string(),
-            buffer_size: 8192,
+    #[test]
+    fn test_flatten_into() {
+        let temp_dir = create_test_directory();
+        fs::write(temp_dir.path().join("subdir__file3.ts"), "Clashing name").unwrap();
+        let flat_dir = TempDir::new().unwrap();
+        let args = |extra: &[&str]| {
+            let mut args = vec![
+                OsStr::new("concacti"),
+                OsStr::new("-d"),
+                temp_dir.path().as_os_str(),
+                OsStr::new("-p"),
+                OsStr::new("**/*.ts"),
+                OsStr::new("-p"),
+                OsStr::new("!**/node_modules/**"),
+                OsStr::new("--flatten-into"),
+                flat_dir.path().as_os_str(),
+            ];
+            args.extend(extra.iter().map(OsStr::new));
+            Cli::parse_from(args)
         };
 
-        concatenate_files(&cli).unwrap();
+        let stats = concatenate_files(&args(&["--dry-run"])).unwrap();
+        assert_eq!(stats.files, 3);
+        assert_eq!(fs::read_dir(flat_dir.path()).unwrap().count(), 0);
 
-        let mut output_content = String::new();
-        File::open(output_file)
+        concatenate_files(&args(&[])).unwrap();
+        let mut names: Vec<_> = fs::read_dir(flat_dir.path())
             .unwrap()
-            .read_to_string(&mut output_content)
-            .unwrap();
-
-        assert!(output_content.contains("subdir"));
-        assert!(output_content.contains("node_modules"));
-        assert!(output_content.contains("file2.ts"));
-        assert!(output_content.contains("file3.ts"));
-        assert!(output_content.contains("file4.ts"));
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            ["file2.ts", "subdir__file3-2.ts", "subdir__file3.ts"]
+        );
+        // The directory sorts before the file whose name clashes with its contents.
+        assert_eq!(
+            fs::read_to_string(flat_dir.path().join("subdir__file3.ts")).unwrap(),
+            "Content of file3"
+        );
     }
 
     #[test]
-    fn test_buffer_size() {
+    fn test_split_round_trip() {
         let temp_dir = create_test_directory();
+        fs::write(
+            temp_dir.path().join("subdir").join("todo.ts"),
+            "// TODO\nlet a = 1;\n// https://example.com/x\n",
+        )
+        .unwrap();
         let output_file = temp_dir.path().join("output.txt");
-
-        let cli = Cli {
-            directory: temp_dir.path().to_path_buf(),
-            output: output_file.clone(),
-            patterns: vec!["**/*.ts".to_string()],
-            max_depth: usize::MAX,
-            write_filenames: false,
-            write_tree: false,
-            comment_style: "//".to_string(),
-            buffer_size: 1, // Minimum buffer size to test buffering
-        };
-
+        let cli = test_cli(temp_dir.path(), &output_file);
         concatenate_files(&cli).unwrap();
 
-        let mut output_content = String::new();
-        File::open(output_file)
-            .unwrap()
-            .read_to_string(&mut output_content)
+        let into = TempDir::new().unwrap();
+        let count = split::split(&fs::read(&output_file).unwrap(), into.path(), "//").unwrap();
+        assert_eq!(count, 5);
+        for file in [
+            "file1.txt",
+            "file2.ts",
+            "subdir/file3.ts",
+            "subdir/todo.ts",
+            "node_modules/file4.ts",
+        ] {
+            assert_eq!(
+                fs::read(into.path().join(file)).unwrap(),
+                fs::read(temp_dir.path().join(file)).unwrap(),
+                "{}",
+                file
+            );
+        }
+    }
+
+    #[test]
+    fn test_split_relative_round_trip() {
+        // Tests share the working directory, so reach the files through a
+        // relative `./name` path instead of changing into them.
+        let temp_dir = tempfile::Builder::new()
+            .prefix("split-test")
+            .tempdir_in(".")
             .unwrap();
+        fs::write(temp_dir.path().join("file1.txt"), "Content of file1").unwrap();
+        fs::create_dir(temp_dir.path().join("subdir")).unwrap();
+        fs::write(
+            temp_dir.path().join("subdir").join("file2.ts"),
+            "Content of file2",
+        )
+        .unwrap();
+        let directory = Path::new(".").join(temp_dir.path().file_name().unwrap());
+        let output_file = TempDir::new().unwrap();
+        let output_file = output_file.path().join("output.txt");
+        concatenate_files(&test_cli(&directory, &output_file)).unwrap();
+
+        let output = fs::read(&output_file).unwrap();
+        let marker = format!("// {}", directory.join("file1.txt").display());
+        assert!(String::from_utf8_lossy(&output).contains(&marker));
+        let into = TempDir::new().unwrap();
+        assert_eq!(split::split(&output, into.path(), "//").unwrap(), 2);
+        for file in ["file1.txt", "subdir/file2.ts"] {
+            assert_eq!(
+                fs::read(into.path().join(file)).unwrap(),
+                fs::read(temp_dir.path().join(file)).unwrap(),
+                "{}",
+                file
+            );
+        }
+    }
 
-        assert!(output_content.contains("Content of file2"));
-        assert!(output_content.contains("Content of file3"));
-        assert!(output_content.contains("Content of file4"));
+    #[test]
+    fn test_list_null_separated() {
+        let temp_dir = create_test_directory();
+        fs::write(temp_dir.path().join("with space.ts"), "").unwrap();
+
+        let cli = Cli::parse_from([
+            OsStr::new("concacti"),
+            OsStr::new("-d"),
+            temp_dir.path().as_os_str(),
+            OsStr::new("-p"),
+            OsStr::new("*.ts"),
+            OsStr::new("-p"),
+            OsStr::new("!**/node_modules/**"),
+            OsStr::new("--list"),
+            OsStr::new("-0"),
+        ]);
+        assert!(cli.list && cli.null_separated);
+
+        let mut listing = Vec::new();
+        let files = Concatenator::new(cli.options).select_files().unwrap();
+        list_paths(&mut listing, &files, b'\0').unwrap();
+
+        let paths: Vec<_> = listing
+            .split(|&b| b == b'\0')
+            .filter(|path| !path.is_empty())
+            .map(|path| String::from_utf8_lossy(path).into_owned())
+            .collect();
+        assert_eq!(paths.len(), 3);
+        assert!(paths.iter().any(|path| path.ends_with("with space.ts")));
+        assert!(listing.ends_with(b"\0"));
+
+        assert!(Cli::try_parse_from(["concacti", "-d", ".", "-0"]).is_err());
+    }
+
+    #[test]
+    fn test_extensions_summary() {
+        let temp_dir = create_test_directory();
+        fs::write(temp_dir.path().join("Makefile"), "all:").unwrap();
+
+        let cli = test_cli(temp_dir.path(), Path::new("-"));
+        let mut table = Vec::new();
+        let files = Concatenator::new(cli.options).select_files().unwrap();
+        list_extensions(&mut table, &files).unwrap();
+
+        let table = String::from_utf8(table).unwrap();
+        assert_eq!(
+            table,
+            "ts      3 files  48 B\n\
+             (none)  1 file    4 B\n\
+             txt     1 file   16 B\n"
+        );
     }
 }
@@ -0,0 +1,71 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::tree::human_size;
+
+/// Minimum time between redraws of the progress line.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A single stderr line counting files read so far, shared by the reader threads.
+pub struct Progress {
+    total: usize,
+    files: AtomicUsize,
+    bytes: AtomicU64,
+    /// When the line was last drawn; also keeps threads from drawing at once.
+    last_drawn: Mutex<Option<Instant>>,
+}
+
+impl Progress {
+    pub fn new(total: usize) -> Self {
+        Progress {
+            total,
+            files: AtomicUsize::new(0),
+            bytes: AtomicU64::new(0),
+            last_drawn: Mutex::new(None),
+        }
+    }
+
+    /// Counts one more file of `bytes` bytes, redrawing the line if enough time has passed.
+    pub fn record(&self, bytes: u64) {
+        self.files.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+
+        // Another thread drawing right now will show much the same numbers.
+        let Ok(mut last_drawn) = self.last_drawn.try_lock() else {
+            return;
+        };
+        if last_drawn.is_some_and(|last| last.elapsed() < REDRAW_INTERVAL) {
+            return;
+        }
+        eprint!("\r{}", self.line());
+        *last_drawn = Some(Instant::now());
+    }
+
+    /// Draws the final counts and ends the line.
+    pub fn finish(&self) {
+        eprintln!("\r{}", self.line());
+    }
+
+    fn line(&self) -> String {
+        format!(
+            "read {}/{} files, {}",
+            self.files.load(Ordering::Relaxed),
+            self.total,
+            human_size(self.bytes.load(Ordering::Relaxed))
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress_line() {
+        let progress = Progress::new(3);
+        progress.record(1000);
+        progress.record(2000);
+        assert_eq!(progress.line(), "read 2/3 files, 2.9 KB");
+    }
+}
@@ -0,0 +1,116 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::fs::DirEntry;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SortMode {
+    /// File name, ignoring case
+    Name,
+    /// Full path, compared component by component
+    #[default]
+    Path,
+    /// File size, smallest first
+    Size,
+    /// Modification time, oldest first
+    Mtime,
+    /// Whatever order the filesystem returns
+    None,
+}
+
+/// Which end `--sort-by-size` starts from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SizeOrder {
+    /// Smallest files first, so the most files fit a budget
+    Smallest,
+    /// Largest files first
+    Largest,
+}
+
+/// How `--traversal` walks the input directories.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Traversal {
+    /// Depth-first: each subdirectory's files come where it sorts among its siblings
+    #[default]
+    Dfs,
+    /// Breadth-first: all files at one level come before any deeper ones
+    Bfs,
+}
+
+/// Sorts the entries of a single directory in place. Ties are broken by
+/// path so the result is stable across platforms.
+pub fn sort_entries(entries: &mut [DirEntry], mode: SortMode) {
+    match mode {
+        SortMode::Name => entries.sort_by_cached_key(|entry| {
+            (
+                entry.file_name().to_string_lossy().to_lowercase(),
+                entry.path(),
+            )
+        }),
+        SortMode::Path => entries.sort_by_key(|entry| entry.path()),
+        SortMode::Size => entries.sort_by_cached_key(|entry| {
+            let len = entry.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+            (len, entry.path())
+        }),
+        SortMode::Mtime => entries.sort_by_cached_key(|entry| {
+            let modified = entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .ok();
+            (modified, entry.path())
+        }),
+        SortMode::None => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn sorted_names(dir: &TempDir, mode: SortMode) -> Vec<String> {
+        let mut entries: Vec<DirEntry> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap())
+            .collect();
+        sort_entries(&mut entries, mode);
+        entries
+            .iter()
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_sort_by_name_and_path() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("B.txt"), "").unwrap();
+        fs::write(temp_dir.path().join("a.txt"), "").unwrap();
+        fs::write(temp_dir.path().join("c.txt"), "").unwrap();
+
+        assert_eq!(
+            sorted_names(&temp_dir, SortMode::Name),
+            ["a.txt", "B.txt", "c.txt"]
+        );
+        assert_eq!(
+            sorted_names(&temp_dir, SortMode::Path),
+            ["B.txt", "a.txt", "c.txt"]
+        );
+    }
+
+    #[test]
+    fn test_sort_by_size() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("a.txt"), "three").unwrap();
+        fs::write(temp_dir.path().join("b.txt"), "1").unwrap();
+        fs::write(temp_dir.path().join("c.txt"), "22").unwrap();
+
+        assert_eq!(
+            sorted_names(&temp_dir, SortMode::Size),
+            ["b.txt", "c.txt", "a.txt"]
+        );
+    }
+}
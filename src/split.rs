@@ -0,0 +1,368 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// Recreates the files of a plain concatenation under `into`, returning how
+/// many were written.
+///
+/// Each file starts at a `COMMENT PATH` line, optionally followed by a
+/// `--checksums` tag, and ends at the next one; anything before the first,
+/// such as the tree, is skipped. So that ordinary comments in the files
+/// aren't taken for markers, a path must be one the tree lists, or without a
+/// tree, look like a file name: no whitespace, and a `.` or `/` but no `://`.
+/// Absolute paths are made relative to the directory they all share. Files
+/// written with `--binary-as-base64` are decoded back to their bytes.
+pub fn split(input: &[u8], into: &Path, comment_style: &str) -> io::Result<usize> {
+    let files = parse(input, comment_style)?;
+    // A run with a relative `--directory` writes markers like `./src/main.rs`.
+    let paths: Vec<&Path> = files.iter().map(|(path, _)| strip_cur_dir(path)).collect();
+    let common = common_ancestor(paths.iter().copied());
+
+    for (&path, (_, contents)) in paths.iter().zip(&files) {
+        let relative = match &common {
+            Some(common) => path.strip_prefix(common).unwrap_or(path),
+            None => path,
+        };
+        if !relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "refusing to write outside {}: {}",
+                    into.display(),
+                    path.display()
+                ),
+            ));
+        }
+        let target = into.join(relative);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(target, contents)?;
+    }
+    Ok(files.len())
+}
+
+/// Splits `input` at its filename comments into paths and contents. Notes
+/// about skipped files end the previous file without starting a new one.
+fn parse(input: &[u8], comment_style: &str) -> io::Result<Vec<(PathBuf, Vec<u8>)>> {
+    let prefix = format!("{} ", comment_style);
+    let tree = TreePaths::parse(input, &prefix);
+    let is_marker = |path: &str| {
+        if tree.is_empty() {
+            !path.contains(char::is_whitespace)
+                && (path.contains('.') || path.contains('/'))
+                && !path.contains("://")
+        } else {
+            tree.contains(Path::new(path))
+        }
+    };
+    let mut files: Vec<(PathBuf, Vec<u8>)> = Vec::new();
+    // Whether lines belong to the last entry of `files`.
+    let mut in_file = false;
+
+    for line in input.split_inclusive(|&b| b == b'\n') {
+        let text = String::from_utf8_lossy(line);
+        let marker = text
+            .trim_end_matches(['\n', '\r'])
+            .strip_prefix(&prefix)
+            .map(strip_checksum);
+        let starts_file = in_file && files.last().is_some_and(|(_, c)| c.is_empty());
+        match marker {
+            // Kept with the contents, and decoded along with them below.
+            Some(note) if starts_file && base64_len(note).is_some() => {
+                if let Some((_, contents)) = files.last_mut() {
+                    contents.extend_from_slice(line);
+                }
+            }
+            Some(note) if note.starts_with('[') => in_file = false,
+            Some(path) if !path.is_empty() && is_marker(path) => {
+                files.push((PathBuf::from(path), Vec::new()));
+                in_file = true;
+            }
+            _ if in_file => {
+                if let Some((_, contents)) = files.last_mut() {
+                    contents.extend_from_slice(line);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Each file is followed by a newline of its own.
+    for (path, contents) in &mut files {
+        if contents.ends_with(b"\n") {
+            contents.pop();
+        }
+        if let Some(decoded) = decode_base64(contents, &prefix) {
+            *contents = decoded.map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: bad base64 contents: {}", path.display(), e),
+                )
+            })?;
+        }
+    }
+    Ok(files)
+}
+
+/// The files drawn in the trees at the top of a concatenation, relative to
+/// their roots, which tell filename comments from comments in the files.
+#[derive(Debug, Default)]
+struct TreePaths {
+    files: Vec<PathBuf>,
+    /// Directories drawn as `name/...` past `--tree-depth`, whose files aren't listed.
+    collapsed: Vec<PathBuf>,
+}
+
+impl TreePaths {
+    /// Reads the trees, in Unicode or ASCII, from the lines before the first comment.
+    fn parse(input: &[u8], prefix: &str) -> Self {
+        let mut paths = TreePaths::default();
+        // The entries of the current tree, with their depth.
+        let mut entries: Vec<(usize, PathBuf)> = Vec::new();
+        let mut ancestors: Vec<String> = Vec::new();
+        for line in input.split(|&b| b == b'\n') {
+            let text = String::from_utf8_lossy(line);
+            if text.starts_with(prefix) {
+                break;
+            }
+            match tree_entry(&text) {
+                Some((depth, name)) => {
+                    ancestors.truncate(depth);
+                    ancestors.push(name.to_string());
+                    entries.push((depth, ancestors.iter().collect()));
+                }
+                None => {
+                    paths.add(std::mem::take(&mut entries));
+                    ancestors.clear();
+                }
+            }
+        }
+        paths.add(entries);
+        paths
+    }
+
+    /// Adds the entries of one tree that have nothing below them.
+    fn add(&mut self, entries: Vec<(usize, PathBuf)>) {
+        for (index, (depth, path)) in entries.iter().enumerate() {
+            if entries.get(index + 1).is_some_and(|(next, _)| next > depth) {
+                continue;
+            }
+            match path.to_str().and_then(|path| path.strip_suffix("/...")) {
+                Some(dir) => self.collapsed.push(PathBuf::from(dir)),
+                None => self.files.push(path.clone()),
+            }
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.collapsed.is_empty()
+    }
+
+    /// Whether `path` ends with one of the files, or lies in a collapsed directory.
+    fn contains(&self, path: &Path) -> bool {
+        self.files.iter().any(|file| path.ends_with(file))
+            || path
+                .ancestors()
+                .skip(1)
+                .any(|ancestor| self.collapsed.iter().any(|dir| ancestor.ends_with(dir)))
+    }
+}
+
+/// The depth and name of the entry a tree line draws, without a `--tree-sizes` size.
+fn tree_entry(line: &str) -> Option<(usize, &str)> {
+    let mut rest = line;
+    let mut depth = 0;
+    while let Some(inner) = ["│   ", "|   ", "    "]
+        .iter()
+        .find_map(|skip| rest.strip_prefix(skip))
+    {
+        rest = inner;
+        depth += 1;
+    }
+    let name = ["├── ", "└── ", "|-- ", "`-- "]
+        .iter()
+        .find_map(|item| rest.strip_prefix(item))?;
+    Some((depth, strip_size(name)))
+}
+
+/// Drops the ` (1.2 KB)` size `--tree-sizes` adds after a name.
+fn strip_size(name: &str) -> &str {
+    match name.rsplit_once(" (") {
+        Some((name, size))
+            if size
+                .strip_suffix(" B)")
+                .or_else(|| {
+                    ["KB)", "MB)", "GB)", "TB)"]
+                        .iter()
+                        .find_map(|unit| size.strip_suffix(unit)?.strip_suffix(' '))
+                })
+                .is_some_and(|number| number.parse::<f64>().is_ok()) =>
+        {
+            name
+        }
+        _ => name,
+    }
+}
+
+/// The byte length in a `[base64, N bytes]` note.
+fn base64_len(note: &str) -> Option<usize> {
+    note.strip_prefix("[base64, ")?
+        .strip_suffix(" bytes]")?
+        .parse()
+        .ok()
+}
+
+/// Decodes `contents` if they start with a `--binary-as-base64` note, checking
+/// the length it gives.
+fn decode_base64(contents: &[u8], prefix: &str) -> Option<Result<Vec<u8>, String>> {
+    let (first, rest) = contents.split_at(contents.iter().position(|&b| b == b'\n')?);
+    let len = base64_len(std::str::from_utf8(first).ok()?.strip_prefix(prefix)?)?;
+    let encoded: Vec<u8> = rest
+        .iter()
+        .copied()
+        .filter(|b| !b.is_ascii_whitespace())
+        .collect();
+    Some(
+        STANDARD
+            .decode(encoded)
+            .map_err(|e| e.to_string())
+            .and_then(|decoded| {
+                if decoded.len() == len {
+                    Ok(decoded)
+                } else {
+                    Err(format!("expected {} bytes, got {}", len, decoded.len()))
+                }
+            }),
+    )
+}
+
+/// Drops the ` [1a2b3c4d]` tag `--checksums` adds after the path.
+fn strip_checksum(text: &str) -> &str {
+    match text.rsplit_once(" [") {
+        Some((path, tag))
+            if tag.len() == 9
+                && tag.ends_with(']')
+                && tag[..8].chars().all(|c| c.is_ascii_hexdigit()) =>
+        {
+            path
+        }
+        _ => text,
+    }
+}
+
+/// `path` without its leading `.` components.
+fn strip_cur_dir(path: &Path) -> &Path {
+    let mut components = path.components();
+    while components.clone().next() == Some(Component::CurDir) {
+        components.next();
+    }
+    components.as_path()
+}
+
+/// The deepest directory containing all of `paths`. A relative one that
+/// climbs out with `..` isn't stripped, so `split` rejects those paths.
+fn common_ancestor<'a>(mut paths: impl Iterator<Item = &'a Path>) -> Option<PathBuf> {
+    let first = paths.next()?;
+    let mut common = first.parent()?.to_path_buf();
+    for path in paths {
+        while !path.starts_with(&common) {
+            common = common.parent()?.to_path_buf();
+        }
+    }
+    let normal = common
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+    (common.is_absolute() || normal).then_some(common)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let input = "src\n\
+            ├── a.rs (1.2 KB)\n\
+            ├── b.bin\n\
+            ├── c.txt\n\
+            └── deep/...\n\n\
+            // src/a.rs [0a1b2c3d]\n\
+            // TODO\n\
+            // https://example.com/x\n\
+            fn a() {}\n\n\
+            // [skipped binary file] src/b.bin\n\
+            // src/c.txt\n\
+            no newline\n\
+            // src/deep/x/y.rs\n\
+            y\n";
+        let files = parse(input.as_bytes(), "//").unwrap();
+        assert_eq!(
+            files,
+            [
+                (
+                    PathBuf::from("src/a.rs"),
+                    b"// TODO\n// https://example.com/x\nfn a() {}\n".to_vec()
+                ),
+                (PathBuf::from("src/c.txt"), b"no newline".to_vec()),
+                (PathBuf::from("src/deep/x/y.rs"), b"y".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_without_tree() {
+        let input = b"// a.rs\n// TODO\n// eslint-disable-next-line\n// http://x.io/a\nx\n";
+        let files = parse(input, "//").unwrap();
+        assert_eq!(
+            files,
+            [(
+                PathBuf::from("a.rs"),
+                b"// TODO\n// eslint-disable-next-line\n// http://x.io/a\nx".to_vec()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_tree_entry() {
+        assert_eq!(
+            tree_entry("│   └── f (1).txt (2 B)"),
+            Some((1, "f (1).txt"))
+        );
+        assert_eq!(tree_entry("|   |-- main.rs (3.5 KB)"), Some((1, "main.rs")));
+        assert_eq!(tree_entry("`-- b/..."), Some((0, "b/...")));
+        assert_eq!(tree_entry("root"), None);
+    }
+
+    #[test]
+    fn test_parse_base64() {
+        let input = b"// img.png\n// [base64, 5 bytes]\nAAEC\n/wA=\n";
+        let files = parse(input, "//").unwrap();
+        assert_eq!(files, [(PathBuf::from("img.png"), vec![0, 1, 2, 0xff, 0])]);
+
+        let input = b"// bad.bin\n// [base64, 4 bytes]\nAAEC/wA=\n";
+        let error = parse(input, "//").unwrap_err();
+        assert!(error.to_string().contains("expected 4 bytes, got 5"));
+    }
+
+    #[test]
+    fn test_common_ancestor() {
+        let paths = [Path::new("/a/b/c.rs"), Path::new("/a/d/e.rs")];
+        assert_eq!(
+            common_ancestor(paths.into_iter()),
+            Some(PathBuf::from("/a"))
+        );
+        let paths = [Path::new("a/b/c.rs"), Path::new("a/d.rs")];
+        assert_eq!(common_ancestor(paths.into_iter()), Some(PathBuf::from("a")));
+        assert_eq!(common_ancestor([Path::new("../a/b.rs")].into_iter()), None);
+        assert_eq!(
+            strip_cur_dir(Path::new("./././a/b.rs")),
+            Path::new("a/b.rs")
+        );
+    }
+}
@@ -0,0 +1,167 @@
+use serde::Deserialize;
+use std::str::FromStr;
+
+/// The `{...}` placeholders a per-file template can use.
+const PLACEHOLDERS: &[(&str, Placeholder)] = &[
+    ("path", Placeholder::Path),
+    ("relpath", Placeholder::Relpath),
+    ("ext", Placeholder::Ext),
+    ("size", Placeholder::Size),
+    ("content", Placeholder::Content),
+    ("lang", Placeholder::Lang),
+    ("index", Placeholder::Index),
+];
+
+/// A per-file template such as `=== {relpath} ({size}) ===\n{content}`,
+/// checked for unknown placeholders when parsed.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Placeholder(Placeholder),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Placeholder {
+    Path,
+    Relpath,
+    Ext,
+    Size,
+    Content,
+    Lang,
+    Index,
+}
+
+/// Values substituted for the placeholders of a [`Template`].
+pub struct TemplateValues<'a> {
+    /// The path as filename comments would show it, for `{path}`.
+    pub path: &'a str,
+    /// The path relative to its input directory, for `{relpath}`.
+    pub relpath: &'a str,
+    pub ext: &'a str,
+    /// The file's size in bytes, for `{size}`.
+    pub size: u64,
+    pub content: &'a [u8],
+    /// The Markdown fence language, for `{lang}`.
+    pub lang: &'a str,
+    /// The file's position in the output, counting from 1, for `{index}`.
+    pub index: usize,
+}
+
+impl FromStr for Template {
+    type Err = String;
+
+    /// Splits `template` into text and placeholders. A `{` that doesn't start
+    /// a `{name}` placeholder is kept as text; an unknown name is an error.
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            text.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let name = rest[1..]
+                .find('}')
+                .map(|end| &rest[1..=end])
+                .filter(|name| {
+                    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                });
+            match name {
+                Some(name) => {
+                    let &(_, placeholder) = PLACEHOLDERS
+                        .iter()
+                        .find(|(known, _)| *known == name)
+                        .ok_or_else(|| {
+                        format!(
+                            "unknown placeholder {{{}}} in template, expected one of {}",
+                            name,
+                            PLACEHOLDERS
+                                .iter()
+                                .map(|(known, _)| format!("{{{}}}", known))
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        )
+                    })?;
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(Part::Placeholder(placeholder));
+                    rest = &rest[name.len() + 2..];
+                }
+                None => {
+                    text.push('{');
+                    rest = &rest[1..];
+                }
+            }
+        }
+        text.push_str(rest);
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        Ok(Template { parts })
+    }
+}
+
+impl TryFrom<String> for Template {
+    type Error = String;
+
+    fn try_from(template: String) -> Result<Self, Self::Error> {
+        template.parse()
+    }
+}
+
+impl Template {
+    /// Renders the template for one file. The contents are copied as they
+    /// are, so placeholders inside them aren't expanded.
+    pub fn render(&self, values: &TemplateValues) -> Vec<u8> {
+        let mut rendered = Vec::with_capacity(values.content.len() + 64);
+        for part in &self.parts {
+            let text = match part {
+                Part::Text(text) => text.as_str(),
+                Part::Placeholder(Placeholder::Path) => values.path,
+                Part::Placeholder(Placeholder::Relpath) => values.relpath,
+                Part::Placeholder(Placeholder::Ext) => values.ext,
+                Part::Placeholder(Placeholder::Lang) => values.lang,
+                Part::Placeholder(Placeholder::Content) => {
+                    rendered.extend_from_slice(values.content);
+                    continue;
+                }
+                Part::Placeholder(Placeholder::Size) => &values.size.to_string(),
+                Part::Placeholder(Placeholder::Index) => &values.index.to_string(),
+            };
+            rendered.extend_from_slice(text.as_bytes());
+        }
+        rendered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_template() {
+        let template: Template = "=== {relpath} ({size}) {x ===\n{content}".parse().unwrap();
+        let values = TemplateValues {
+            path: "/repo/src/main.rs",
+            relpath: "src/main.rs",
+            ext: "rs",
+            size: 12,
+            content: b"let s = \"{path}\";\n",
+            lang: "rust",
+            index: 1,
+        };
+        assert_eq!(
+            String::from_utf8(template.render(&values)).unwrap(),
+            "=== src/main.rs (12) {x ===\nlet s = \"{path}\";\n"
+        );
+
+        let error = "{name}: {content}".parse::<Template>().unwrap_err();
+        assert!(error.contains("unknown placeholder {name}"));
+    }
+}
@@ -0,0 +1,21 @@
+/// Rough number of bytes per token for typical source code and prose.
+pub const BYTES_PER_TOKEN: u64 = 4;
+
+/// Estimates how many tokens a language model would see in `bytes` bytes of text.
+pub fn estimate_tokens(bytes: u64) -> u64 {
+    bytes.div_ceil(BYTES_PER_TOKEN)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate_tokens() {
+        assert_eq!(estimate_tokens(0), 0);
+        assert_eq!(estimate_tokens(1), 1);
+        assert_eq!(estimate_tokens(4), 1);
+        assert_eq!(estimate_tokens(5), 2);
+        assert_eq!(estimate_tokens(4000), 1000);
+    }
+}
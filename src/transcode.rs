@@ -0,0 +1,166 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::io::{self, Write};
+
+/// Character encoding the output is written in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputEncoding {
+    /// UTF-8 without a byte order mark
+    #[default]
+    Utf8,
+    /// UTF-16 little-endian, starting with a byte order mark
+    Utf16le,
+    /// UTF-16 big-endian, starting with a byte order mark
+    Utf16be,
+    /// ISO-8859-1; characters outside it are written as `?`, with a warning
+    Latin1,
+}
+
+/// Converts the UTF-8 written to it into `encoding` before passing it on.
+/// Invalid UTF-8 is replaced with U+FFFD; a character split across writes is
+/// held back until the rest of it arrives.
+///
+/// This is done by hand rather than with `encoding_rs`, whose encoders follow
+/// the WHATWG Encoding Standard: they write UTF-8 when asked for UTF-16, and
+/// treat Latin-1 as windows-1252, so neither output would be what it says.
+pub struct Transcoder<W> {
+    inner: W,
+    encoding: OutputEncoding,
+    /// Bytes of an incomplete character at the end of the last write.
+    pending: Vec<u8>,
+    started: bool,
+    /// Characters the encoding can't represent, written as `?` instead.
+    unrepresentable: usize,
+}
+
+impl<W: Write> Transcoder<W> {
+    pub fn new(inner: W, encoding: OutputEncoding) -> Self {
+        Transcoder {
+            inner,
+            encoding,
+            pending: Vec::new(),
+            started: false,
+            unrepresentable: 0,
+        }
+    }
+
+    /// Writes out anything held back and flushes the inner writer. Returns
+    /// how many characters couldn't be represented and were written as `?`.
+    pub fn finish(mut self) -> io::Result<usize> {
+        if !self.pending.is_empty() {
+            self.pending.clear();
+            self.encode(&char::REPLACEMENT_CHARACTER.to_string())?;
+        }
+        self.inner.flush()?;
+        Ok(self.unrepresentable)
+    }
+
+    fn encode(&mut self, text: &str) -> io::Result<()> {
+        if !self.started {
+            self.started = true;
+            match self.encoding {
+                OutputEncoding::Utf16le => self.inner.write_all(&[0xFF, 0xFE])?,
+                OutputEncoding::Utf16be => self.inner.write_all(&[0xFE, 0xFF])?,
+                _ => {}
+            }
+        }
+
+        let encoded: Vec<u8> = match self.encoding {
+            OutputEncoding::Utf8 => text.as_bytes().to_vec(),
+            OutputEncoding::Utf16le => text.encode_utf16().flat_map(u16::to_le_bytes).collect(),
+            OutputEncoding::Utf16be => text.encode_utf16().flat_map(u16::to_be_bytes).collect(),
+            OutputEncoding::Latin1 => text
+                .chars()
+                .map(|c| {
+                    u8::try_from(u32::from(c)).unwrap_or_else(|_| {
+                        self.unrepresentable += 1;
+                        b'?'
+                    })
+                })
+                .collect(),
+        };
+        self.inner.write_all(&encoded)
+    }
+}
+
+impl<W: Write> Write for Transcoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.encoding == OutputEncoding::Utf8 {
+            return self.inner.write(buf);
+        }
+
+        self.pending.extend_from_slice(buf);
+        let mut rest = std::mem::take(&mut self.pending);
+        loop {
+            match std::str::from_utf8(&rest) {
+                Ok(text) => {
+                    self.encode(text)?;
+                    rest.clear();
+                    break;
+                }
+                Err(error) => {
+                    let valid = error.valid_up_to();
+                    // Only produced by `from_utf8` for the valid prefix.
+                    let text = std::str::from_utf8(&rest[..valid]).unwrap_or_default();
+                    self.encode(text)?;
+                    match error.error_len() {
+                        Some(len) => {
+                            self.encode(&char::REPLACEMENT_CHARACTER.to_string())?;
+                            rest.drain(..valid + len);
+                        }
+                        None => {
+                            rest.drain(..valid);
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        self.pending = rest;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transcode(chunks: &[&[u8]], encoding: OutputEncoding) -> Vec<u8> {
+        transcode_counting(chunks, encoding).0
+    }
+
+    fn transcode_counting(chunks: &[&[u8]], encoding: OutputEncoding) -> (Vec<u8>, usize) {
+        let mut output = Vec::new();
+        let mut transcoder = Transcoder::new(&mut output, encoding);
+        for chunk in chunks {
+            transcoder.write_all(chunk).unwrap();
+        }
+        let unrepresentable = transcoder.finish().unwrap();
+        (output, unrepresentable)
+    }
+
+    #[test]
+    fn test_transcoder() {
+        // "é" is split across the two writes.
+        let chunks: &[&[u8]] = &[b"caf\xC3", b"\xA9 \xFF!"];
+        assert_eq!(
+            transcode(chunks, OutputEncoding::Utf16le),
+            [0xFF, 0xFE, b'c', 0, b'a', 0, b'f', 0, 0xE9, 0, b' ', 0, 0xFD, 0xFF, b'!', 0]
+        );
+        assert_eq!(
+            transcode(&[b"\xC3\xA9\xE2\x82\xAC"], OutputEncoding::Utf16be),
+            [0xFE, 0xFF, 0, 0xE9, 0x20, 0xAC]
+        );
+        assert_eq!(
+            transcode_counting(&["é€→".as_bytes()], OutputEncoding::Latin1),
+            (vec![0xE9, b'?', b'?'], 2)
+        );
+        assert_eq!(transcode(&[b"a\xC3"], OutputEncoding::Latin1), b"a?");
+        assert_eq!(transcode(&[b"as is"], OutputEncoding::Utf8), b"as is");
+    }
+}
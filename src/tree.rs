@@ -1,26 +1,224 @@
-use std::fs::{self};
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::fs::{self, DirEntry};
 use std::io::{self};
 use std::path::Path;
-use termtree::Tree;
+use termtree::{GlyphPalette, Tree};
 
+/// Connectors made of plain ASCII, e.g. `|-- dir` and `` `-- file``.
+const ASCII_GLYPHS: GlyphPalette = GlyphPalette {
+    middle_item: "|",
+    last_item: "`",
+    item_indent: "-- ",
+    middle_skip: "|",
+    last_skip: " ",
+    skip_indent: "   ",
+};
+
+/// Returns the last component of `p`, or the whole path when it has none
+/// (such as `/` or `..`). Invalid UTF-8 is replaced rather than panicking.
 fn label<P: AsRef<Path>>(p: P) -> String {
-    p.as_ref().file_name().unwrap().to_str().unwrap().to_owned()
+    let path = p.as_ref();
+    match path.file_name() {
+        Some(name) => name.to_string_lossy().into_owned(),
+        None => path.to_string_lossy().into_owned(),
+    }
+}
+
+/// Order of the entries within each directory of the tree.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TreeSort {
+    /// Directories, then files, each by name
+    #[default]
+    DirsFirst,
+    /// Files, then directories, each by name
+    FilesFirst,
+    /// By name only
+    Alpha,
+}
+
+/// How the tree is ordered and labelled.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TreeOptions {
+    /// Order of each directory's entries.
+    pub sort: TreeSort,
+    /// Append each file's human-readable size, e.g. `main.rs (1.2 KB)`.
+    pub sizes: bool,
+    /// Collapse directories more than this many levels below the root into a
+    /// single `name/...` node. With 0 only the root's own entries are shown.
+    pub max_depth: Option<usize>,
+}
+
+/// The label for a file of `len` bytes.
+fn file_label<P: AsRef<Path>>(p: P, len: u64, options: &TreeOptions) -> String {
+    if options.sizes {
+        format!("{} ({})", label(p), human_size(len))
+    } else {
+        label(p)
+    }
+}
+
+/// Formats `bytes` with a binary unit, e.g. `512 B`, `1.2 KB` or `3.0 MB`.
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
 }
 
-pub fn tree<P: AsRef<Path>>(p: P) -> io::Result<Tree<String>> {
-    let result = fs::read_dir(&p)?.filter_map(|e| e.ok()).fold(
-        Tree::new(label(p.as_ref().canonicalize()?)),
-        |mut root, entry| {
-            let dir = entry.metadata().unwrap();
-            if dir.is_dir() {
-                root.push(tree(entry.path()).unwrap());
+/// The tree of everything under `p`, except each entry for which `skip`
+/// returns true, along with everything below it.
+pub fn tree<P, F>(p: P, options: &TreeOptions, skip: F) -> io::Result<Tree<String>>
+where
+    P: AsRef<Path>,
+    F: Fn(&DirEntry) -> bool,
+{
+    subtree(p.as_ref(), options, 0, &skip)
+}
+
+/// The tree for `p`, which is `depth` directories below the root.
+fn subtree(
+    p: &Path,
+    options: &TreeOptions,
+    depth: usize,
+    skip: &dyn Fn(&DirEntry) -> bool,
+) -> io::Result<Tree<String>> {
+    let mut entries: Vec<DirEntry> = fs::read_dir(p)?
+        .filter_map(|e| e.ok())
+        .filter(|entry| !skip(entry))
+        .collect();
+    sort_entries(&mut entries, options.sort);
+
+    let result =
+        entries
+            .into_iter()
+            .fold(Tree::new(label(p.canonicalize()?)), |mut root, entry| {
+                let node = match entry.metadata() {
+                    Ok(metadata) if metadata.is_dir() => {
+                        if is_too_deep(depth, options) {
+                            Tree::new(truncated_label(entry.path()))
+                        } else {
+                            subtree(&entry.path(), options, depth + 1, skip)
+                                .unwrap_or_else(|e| error_leaf(entry.path(), &e))
+                        }
+                    }
+                    Ok(metadata) => Tree::new(file_label(entry.path(), metadata.len(), options)),
+                    Err(e) => error_leaf(entry.path(), &e),
+                };
+                root.push(node);
+                root
+            });
+    Ok(result)
+}
+
+fn sort_entries(entries: &mut [DirEntry], sort: TreeSort) {
+    entries.sort_by_cached_key(|entry| {
+        // `DirEntry::metadata` doesn't follow symlinks, so a linked directory is
+        // sorted among the files, just as `subtree` draws it as a leaf.
+        let is_dir = entry.metadata().is_ok_and(|metadata| metadata.is_dir());
+        let group = match sort {
+            TreeSort::DirsFirst => !is_dir,
+            TreeSort::FilesFirst => is_dir,
+            TreeSort::Alpha => false,
+        };
+        (group, entry.file_name())
+    });
+}
+
+/// Builds a tree rooted at `p` that only contains `files` and the directories
+/// leading to them, ordered by `options.sort`. Files outside `p` are ignored.
+pub fn filtered_tree<P: AsRef<Path>>(
+    p: P,
+    files: &[&Path],
+    options: &TreeOptions,
+) -> io::Result<Tree<String>> {
+    let mut root = Tree::new(label(p.as_ref().canonicalize()?));
+
+    for file in files {
+        let Ok(relative) = file.strip_prefix(&p) else {
+            continue;
+        };
+
+        let mut node = &mut root;
+        let mut components = relative.iter().peekable();
+        let mut depth = 0;
+        while let Some(component) = components.next() {
+            let is_file = components.peek().is_none();
+            let truncated = !is_file && is_too_deep(depth, options);
+            let name = if truncated {
+                truncated_label(component)
+            } else if is_file && options.sizes {
+                let len = fs::metadata(file).map_or(0, |metadata| metadata.len());
+                file_label(component, len, options)
             } else {
-                root.push(Tree::new(label(entry.path())));
+                component.to_string_lossy().into_owned()
+            };
+            let index = match node.leaves.iter().position(|leaf| leaf.root == name) {
+                Some(index) => index,
+                None => {
+                    node.push(Tree::new(name));
+                    node.leaves.len() - 1
+                }
+            };
+            node = &mut node.leaves[index];
+            if truncated {
+                break;
             }
-            root
-        },
-    );
-    Ok(result)
+            depth += 1;
+        }
+    }
+
+    sort_leaves(&mut root, options.sort);
+    Ok(root)
+}
+
+/// Orders `filtered_tree`'s nodes the way `sort_entries` orders a directory
+/// listing. A node is a directory if it has children or was collapsed.
+fn sort_leaves(tree: &mut Tree<String>, sort: TreeSort) {
+    tree.leaves.sort_by_cached_key(|leaf| {
+        let is_dir = !leaf.leaves.is_empty() || leaf.root.ends_with("/...");
+        let group = match sort {
+            TreeSort::DirsFirst => !is_dir,
+            TreeSort::FilesFirst => is_dir,
+            TreeSort::Alpha => false,
+        };
+        (group, leaf.root.clone())
+    });
+    for leaf in &mut tree.leaves {
+        sort_leaves(leaf, sort);
+    }
+}
+
+/// Switches `tree` and all of its descendants to ASCII connectors, since
+/// termtree draws each node with its own glyphs.
+pub fn use_ascii_glyphs(tree: &mut Tree<String>) {
+    tree.set_glyphs(ASCII_GLYPHS);
+    for leaf in &mut tree.leaves {
+        use_ascii_glyphs(leaf);
+    }
+}
+
+/// Whether a directory `depth` levels below the root is past `--tree-depth`.
+fn is_too_deep(depth: usize, options: &TreeOptions) -> bool {
+    options.max_depth.is_some_and(|max| depth >= max)
+}
+
+/// The label of a directory whose contents are left out, e.g. `src/...`.
+fn truncated_label<P: AsRef<Path>>(p: P) -> String {
+    format!("{}/...", label(p))
+}
+
+/// A leaf standing in for an entry that couldn't be read, e.g. `secret [permission denied]`.
+fn error_leaf<P: AsRef<Path>>(p: P, error: &io::Error) -> Tree<String> {
+    Tree::new(format!("{} [{}]", label(p), error.kind()))
 }
 
 #[cfg(test)]
@@ -57,10 +255,37 @@ mod tests {
         assert_eq!(label(path), "test");
     }
 
+    #[test]
+    fn test_label_without_file_name() {
+        assert_eq!(label(Path::new("/")), "/");
+        assert_eq!(label(Path::new("..")), "..");
+        assert_eq!(label(Path::new("../..")), "../..");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_file_name() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let name = OsStr::from_bytes(b"invalid\xff.txt");
+        // Some filesystems (e.g. on macOS) reject non-UTF-8 names outright.
+        if fs::write(temp_dir.path().join(name), "contents").is_err() {
+            return;
+        }
+
+        assert_eq!(label(Path::new(name)), "invalid\u{FFFD}.txt");
+        let tree_string = tree(temp_dir.path(), &TreeOptions::default(), |_| false)
+            .unwrap()
+            .to_string();
+        assert!(tree_string.contains("invalid\u{FFFD}.txt"));
+    }
+
     #[test]
     fn test_tree_root() {
         let temp_dir = create_test_directory();
-        let tree_result = tree(temp_dir.path()).unwrap();
+        let tree_result = tree(temp_dir.path(), &TreeOptions::default(), |_| false).unwrap();
 
         assert_eq!(
             tree_result.root,
@@ -77,7 +302,7 @@ mod tests {
     #[test]
     fn test_tree_structure() {
         let temp_dir = create_test_directory();
-        let tree_result = tree(temp_dir.path()).unwrap();
+        let tree_result = tree(temp_dir.path(), &TreeOptions::default(), |_| false).unwrap();
 
         let tree_string = tree_result.to_string();
         println!("Tree structure:\n{}", tree_string);
@@ -96,7 +321,7 @@ mod tests {
     // #[test]
     // fn test_tree_depth() {
     //     let temp_dir = create_test_directory();
-    //     let tree_result = tree(temp_dir.path()).unwrap();
+    //     let tree_result = tree(temp_dir.path(), &TreeOptions::default()).unwrap();
 
     //     let tree_string = tree_result.to_string();
     //     let lines: Vec<&str> = tree_string.lines().collect();
@@ -114,7 +339,7 @@ mod tests {
     #[test]
     fn test_empty_directory() {
         let temp_dir = TempDir::new().unwrap();
-        let tree_result = tree(temp_dir.path()).unwrap();
+        let tree_result = tree(temp_dir.path(), &TreeOptions::default(), |_| false).unwrap();
 
         let tree_string = tree_result.to_string();
         assert_eq!(
@@ -124,9 +349,211 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_human_size() {
+        assert_eq!(human_size(0), "0 B");
+        assert_eq!(human_size(1023), "1023 B");
+        assert_eq!(human_size(1024), "1.0 KB");
+        assert_eq!(human_size(1229), "1.2 KB");
+        assert_eq!(human_size(1024 * 1024 - 1), "1024.0 KB");
+        assert_eq!(human_size(1024 * 1024), "1.0 MB");
+        assert_eq!(human_size(5 * 1024 * 1024 * 1024), "5.0 GB");
+    }
+
+    #[test]
+    fn test_tree_sizes() {
+        let temp_dir = create_test_directory();
+        let options = TreeOptions {
+            sizes: true,
+            ..TreeOptions::default()
+        };
+        let tree_string = tree(temp_dir.path(), &options, |_| false)
+            .unwrap()
+            .to_string();
+        assert!(tree_string.contains("file1.txt (16 B)"));
+        assert!(tree_string.contains("dir1\n"));
+
+        let file2 = temp_dir.path().join("dir1").join("file2.txt");
+        let filtered = filtered_tree(temp_dir.path(), &[file2.as_path()], &options).unwrap();
+        assert_eq!(filtered.leaves[0].leaves[0].root, "file2.txt (16 B)");
+    }
+
+    #[test]
+    fn test_tree_depth() {
+        let temp_dir = create_test_directory();
+        let options = TreeOptions {
+            max_depth: Some(1),
+            ..TreeOptions::default()
+        };
+        let tree_string = tree(temp_dir.path(), &options, |_| false)
+            .unwrap()
+            .to_string();
+        assert!(tree_string.contains("file2.txt"));
+        assert!(tree_string.contains("subdir1/..."));
+        assert!(!tree_string.contains("file4.txt"));
+
+        let path = temp_dir.path();
+        let file4 = path.join("dir1").join("subdir1").join("file4.txt");
+        let file1 = path.join("file1.txt");
+        let options = TreeOptions {
+            max_depth: Some(0),
+            ..TreeOptions::default()
+        };
+        let filtered = filtered_tree(path, &[file4.as_path(), file1.as_path()], &options).unwrap();
+        assert_eq!(filtered.leaves[0].root, "dir1/...");
+        assert!(filtered.leaves[0].leaves.is_empty());
+        assert_eq!(filtered.leaves[1].root, "file1.txt");
+    }
+
+    #[test]
+    fn test_tree_skip() {
+        let temp_dir = create_test_directory();
+        let skip = |entry: &DirEntry| entry.file_name() == "dir1";
+        let tree_string = tree(temp_dir.path(), &TreeOptions::default(), skip)
+            .unwrap()
+            .to_string();
+
+        assert!(!tree_string.contains("dir1"));
+        assert!(!tree_string.contains("file2.txt"));
+        assert!(tree_string.contains("file3.txt"));
+    }
+
+    #[test]
+    fn test_tree_sort() {
+        let temp_dir = create_test_directory();
+        fs::write(temp_dir.path().join("a.txt"), "").unwrap();
+        let path = temp_dir.path();
+        let (file1, file3, a) = (
+            path.join("file1.txt"),
+            path.join("dir2").join("file3.txt"),
+            path.join("a.txt"),
+        );
+        let files = [file1.as_path(), file3.as_path(), a.as_path()];
+        let top_level = |tree_result: Tree<String>| {
+            tree_result
+                .leaves
+                .iter()
+                .map(|leaf| leaf.root.clone())
+                .collect::<Vec<_>>()
+        };
+        let top_levels = |sort| {
+            let options = TreeOptions {
+                sort,
+                ..TreeOptions::default()
+            };
+            (
+                top_level(tree(path, &options, |_| false).unwrap()),
+                top_level(filtered_tree(path, &files, &options).unwrap()),
+            )
+        };
+
+        let (full, filtered) = top_levels(TreeSort::DirsFirst);
+        assert_eq!(full, ["dir1", "dir2", "a.txt", "file1.txt"]);
+        assert_eq!(filtered, ["dir2", "a.txt", "file1.txt"]);
+        let (full, filtered) = top_levels(TreeSort::FilesFirst);
+        assert_eq!(full, ["a.txt", "file1.txt", "dir1", "dir2"]);
+        assert_eq!(filtered, ["a.txt", "file1.txt", "dir2"]);
+        let (full, filtered) = top_levels(TreeSort::Alpha);
+        assert_eq!(full, ["a.txt", "dir1", "dir2", "file1.txt"]);
+        assert_eq!(filtered, ["a.txt", "dir2", "file1.txt"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_tree_sort_symlinked_dir() {
+        let temp_dir = create_test_directory();
+        std::os::unix::fs::symlink(temp_dir.path().join("dir2"), temp_dir.path().join("link"))
+            .unwrap();
+        let tree_result = tree(temp_dir.path(), &TreeOptions::default(), |_| false).unwrap();
+
+        let top_level: Vec<_> = tree_result.leaves.iter().map(|leaf| &leaf.root).collect();
+        assert_eq!(top_level, ["dir1", "dir2", "file1.txt", "link"]);
+        assert!(tree_result.leaves[3].leaves.is_empty());
+    }
+
+    #[test]
+    fn test_error_leaf() {
+        let error = io::Error::from(io::ErrorKind::PermissionDenied);
+        let leaf = error_leaf(Path::new("/tmp/secret"), &error);
+        assert_eq!(leaf.root, "secret [permission denied]");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unreadable_subdirectory() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = create_test_directory();
+        let locked = temp_dir.path().join("locked");
+        fs::create_dir(&locked).unwrap();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+
+        let readable = fs::read_dir(&locked).is_ok();
+        let tree_result = tree(temp_dir.path(), &TreeOptions::default(), |_| false);
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let tree_string = tree_result.unwrap().to_string();
+        assert!(tree_string.contains("file4.txt"));
+        // Permissions aren't enforced when running as root.
+        if !readable {
+            assert!(tree_string.contains("locked [permission denied]"));
+        }
+    }
+
+    #[test]
+    fn test_filtered_tree() {
+        let temp_dir = create_test_directory();
+        let path = temp_dir.path();
+        let file4 = path.join("dir1").join("subdir1").join("file4.txt");
+        let file2 = path.join("dir1").join("file2.txt");
+        let files = [
+            file4.as_path(),
+            file2.as_path(),
+            Path::new("/elsewhere/file.txt"),
+        ];
+
+        let tree_result = filtered_tree(path, &files, &TreeOptions::default()).unwrap();
+
+        assert_eq!(tree_result.leaves.len(), 1);
+        let dir1 = &tree_result.leaves[0];
+        assert_eq!(dir1.root, "dir1");
+        assert_eq!(dir1.leaves[0].root, "subdir1");
+        assert_eq!(dir1.leaves[0].leaves[0].root, "file4.txt");
+        assert_eq!(dir1.leaves[1].root, "file2.txt");
+
+        let tree_string = tree_result.to_string();
+        assert!(!tree_string.contains("dir2"));
+        assert!(!tree_string.contains("file1.txt"));
+        assert!(!tree_string.contains("elsewhere"));
+    }
+
+    #[test]
+    fn test_ascii_glyphs() {
+        let temp_dir = create_test_directory();
+        let mut tree_result = tree(temp_dir.path(), &TreeOptions::default(), |_| false).unwrap();
+        use_ascii_glyphs(&mut tree_result);
+
+        let expected = format!(
+            "{}\n\
+             |-- dir1\n\
+             |   |-- subdir1\n\
+             |   |   `-- file4.txt\n\
+             |   `-- file2.txt\n\
+             |-- dir2\n\
+             |   `-- file3.txt\n\
+             `-- file1.txt\n",
+            tree_result.root
+        );
+        assert_eq!(tree_result.to_string(), expected);
+    }
+
     #[test]
     fn test_nonexistent_directory() {
-        let result = tree(Path::new("/nonexistent/directory"));
+        let result = tree(
+            Path::new("/nonexistent/directory"),
+            &TreeOptions::default(),
+            |_| false,
+        );
         assert!(
             result.is_err(),
             "Attempting to create a tree for a nonexistent directory should return an error"
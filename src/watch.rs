@@ -0,0 +1,155 @@
+use notify::{Event, RecursiveMode, Watcher};
+use std::fs;
+use std::io::{self};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use concacti::FileFilter;
+
+use crate::{concatenate_files, status, temp_path, Cli};
+
+/// How long the input must stay quiet before a burst of events triggers a rebuild.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Watches the input directories and regenerates the output whenever a
+/// matching file changes. Runs until the watcher shuts down.
+pub fn watch(cli: &Cli) -> io::Result<()> {
+    let file_filter = cli.options.file_filter()?;
+    let output = cli.output.as_deref().filter(|path| *path != Path::new("-"));
+    let output_path = match output
+        .or(cli.output_dir.as_deref())
+        .or(cli.flatten_into.as_deref())
+    {
+        Some(output) => Some(fs::canonicalize(output)?),
+        None => None,
+    };
+
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx).map_err(io::Error::other)?;
+    for directory in &cli.options.directories {
+        watcher
+            .watch(directory, RecursiveMode::Recursive)
+            .map_err(io::Error::other)?;
+    }
+
+    let event_is_relevant = |event: notify::Result<Event>| {
+        event.is_ok_and(|event| {
+            event
+                .paths
+                .iter()
+                .any(|path| is_relevant(cli, &file_filter, output_path.as_deref(), path))
+        })
+    };
+
+    while let Ok(event) = rx.recv() {
+        let mut relevant = event_is_relevant(event);
+        loop {
+            match rx.recv_timeout(DEBOUNCE) {
+                Ok(event) => relevant |= event_is_relevant(event),
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+        }
+
+        if relevant {
+            let stats = concatenate_files(cli)?;
+            status(
+                cli,
+                format_args!("[{}] regenerated output ({})", timestamp(), stats),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether a change to `path` should trigger a rebuild: it must pass the
+/// filter, not be hidden (unless `--hidden`), and not be the output itself,
+/// the temporary file it is written to first, or inside the output directory.
+fn is_relevant(
+    cli: &Cli,
+    file_filter: &FileFilter,
+    output_path: Option<&Path>,
+    path: &Path,
+) -> bool {
+    if let Some(output_path) = output_path {
+        // The file may have been deleted, so resolve its parent instead.
+        let resolved = path
+            .parent()
+            .and_then(|parent| fs::canonicalize(parent).ok())
+            .zip(path.file_name())
+            .map(|(parent, name)| parent.join(name));
+        if resolved.is_some_and(|resolved| {
+            resolved.starts_with(output_path) || resolved == temp_path(output_path)
+        }) {
+            return false;
+        }
+    }
+
+    if !cli.options.hidden {
+        let relative = cli
+            .options
+            .directories
+            .iter()
+            .find_map(|directory| relative_to_root(directory, path))
+            .unwrap_or_else(|| path.to_path_buf());
+        if relative
+            .iter()
+            .any(|component| component.as_encoded_bytes().starts_with(b"."))
+        {
+            return false;
+        }
+    }
+
+    file_filter.should_process(path)
+}
+
+fn relative_to_root(directory: &Path, path: &Path) -> Option<PathBuf> {
+    let root = fs::canonicalize(directory).ok()?;
+    path.strip_prefix(&root)
+        .or_else(|_| path.strip_prefix(directory))
+        .ok()
+        .map(Path::to_path_buf)
+}
+
+/// Current UTC time of day as `HH:MM:SS`.
+fn timestamp() -> String {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+        % 86400;
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_is_relevant() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = fs::canonicalize(temp_dir.path()).unwrap();
+        let output_file = root.join("output.txt");
+        fs::write(&output_file, "").unwrap();
+
+        let cli = Cli::parse_from(["concacti", "-d", root.to_str().unwrap(), "-p", "**/*.ts"]);
+        let file_filter = cli.options.file_filter().unwrap();
+        let check = |path: &Path| is_relevant(&cli, &file_filter, Some(&output_file), path);
+
+        assert!(check(&root.join("src").join("main.ts")));
+        assert!(check(&root.join("deleted.ts")));
+        assert!(!check(&root.join("notes.txt")));
+        assert!(!check(&root.join(".git").join("hook.ts")));
+        assert!(!check(&output_file));
+        assert!(!check(&temp_path(&output_file)));
+    }
+}
@@ -0,0 +1,178 @@
+use std::io::{self, Write};
+use std::path::Path;
+use termtree::Tree;
+
+use crate::Stats;
+
+/// Opens the `<files>` element, including the `prepend` text, the rendered
+/// `header` and a `<tree>` with one node per input directory if given.
+pub fn write_start<W: Write>(
+    writer: &mut W,
+    prepend: Option<&str>,
+    header: Option<&str>,
+    trees: Option<&[Tree<String>]>,
+) -> io::Result<()> {
+    writeln!(writer, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+    writeln!(writer, "<files>")?;
+    if let Some(prepend) = prepend {
+        write_text_element(writer, "prepend", prepend)?;
+    }
+    if let Some(header) = header {
+        write_text_element(writer, "header", header)?;
+    }
+    if let Some(trees) = trees {
+        writeln!(writer, "  <tree>")?;
+        for tree in trees {
+            write_tree_node(writer, tree, 2)?;
+        }
+        writeln!(writer, "  </tree>")?;
+    }
+    Ok(())
+}
+
+fn write_tree_node<W: Write>(writer: &mut W, tree: &Tree<String>, depth: usize) -> io::Result<()> {
+    let indent = "  ".repeat(depth);
+    let name = escape_attribute(&tree.root);
+    if tree.leaves.is_empty() {
+        return writeln!(writer, "{}<node name=\"{}\"/>", indent, name);
+    }
+    writeln!(writer, "{}<node name=\"{}\">", indent, name)?;
+    for leaf in &tree.leaves {
+        write_tree_node(writer, leaf, depth + 1)?;
+    }
+    writeln!(writer, "{}</node>", indent)
+}
+
+/// Writes a `<file>` element with the contents in a CDATA section.
+/// Invalid UTF-8 is replaced rather than rejected.
+pub fn write_file<W: Write>(
+    writer: &mut W,
+    path: &Path,
+    size: u64,
+    contents: &[u8],
+) -> io::Result<()> {
+    write!(
+        writer,
+        "  <file path=\"{}\" size=\"{}\">",
+        escape_attribute(&path.to_string_lossy()),
+        size
+    )?;
+    write_cdata(writer, &String::from_utf8_lossy(contents))?;
+    writeln!(writer, "</file>")
+}
+
+/// Writes an empty `<file>` element saying why the file was left out.
+pub fn write_skipped_file<W: Write>(
+    writer: &mut W,
+    path: &Path,
+    size: u64,
+    note: &str,
+) -> io::Result<()> {
+    writeln!(
+        writer,
+        "  <file path=\"{}\" size=\"{}\" skipped=\"{}\"/>",
+        escape_attribute(&path.to_string_lossy()),
+        size,
+        escape_attribute(note)
+    )
+}
+
+/// Closes the `<files>` element, adding `summary` and the `append` text if given.
+pub fn write_end<W: Write>(
+    writer: &mut W,
+    summary: Option<&Stats>,
+    append: Option<&str>,
+) -> io::Result<()> {
+    if let Some(summary) = summary {
+        writeln!(
+            writer,
+            "  <summary files=\"{}\" skipped=\"{}\" bytes=\"{}\"/>",
+            summary.files,
+            summary.skipped.len(),
+            summary.bytes
+        )?;
+    }
+    if let Some(append) = append {
+        write_text_element(writer, "append", append)?;
+    }
+    writeln!(writer, "</files>")
+}
+
+fn write_text_element<W: Write>(writer: &mut W, name: &str, text: &str) -> io::Result<()> {
+    write!(writer, "  <{}>", name)?;
+    write_cdata(writer, text)?;
+    writeln!(writer, "</{}>", name)
+}
+
+/// Wraps `text` in CDATA, splitting the section wherever `text` contains
+/// `]]>`, which would otherwise end it early. Control characters XML doesn't
+/// allow anywhere are replaced with U+FFFD.
+fn write_cdata<W: Write>(writer: &mut W, text: &str) -> io::Result<()> {
+    let text: String = text
+        .chars()
+        .map(|c| match c {
+            '\t' | '\n' | '\r' => c,
+            c if c < ' ' => char::REPLACEMENT_CHARACTER,
+            c => c,
+        })
+        .collect();
+    write!(
+        writer,
+        "<![CDATA[{}]]>",
+        text.replace("]]>", "]]]]><![CDATA[>")
+    )
+}
+
+fn escape_attribute(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\n' => escaped.push_str("&#10;"),
+            c if c < ' ' && c != '\t' => escaped.push(char::REPLACEMENT_CHARACTER),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_xml_document() {
+        let mut tree = Tree::new("root".to_string());
+        tree.push(Tree::new("a&b.txt".to_string()));
+
+        let mut output = Vec::new();
+        write_start(&mut output, None, Some("# Project"), Some(&[tree])).unwrap();
+        write_file(&mut output, Path::new("root/a&b.txt"), 12, b"if a]]>b {}\n").unwrap();
+        write_skipped_file(
+            &mut output,
+            Path::new("root/c.bin"),
+            3,
+            "skipped binary file",
+        )
+        .unwrap();
+        write_end(&mut output, None, None).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <files>\n  \
+             <header><![CDATA[# Project]]></header>\n  \
+             <tree>\n    \
+             <node name=\"root\">\n      \
+             <node name=\"a&amp;b.txt\"/>\n    \
+             </node>\n  \
+             </tree>\n  \
+             <file path=\"root/a&amp;b.txt\" size=\"12\"><![CDATA[if a]]]]><![CDATA[>b {}\n]]></file>\n  \
+             <file path=\"root/c.bin\" size=\"3\" skipped=\"skipped binary file\"/>\n\
+             </files>\n"
+        );
+    }
+}